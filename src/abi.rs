@@ -12,30 +12,67 @@
  */
 use anyhow::format_err;
 use ever_abi::{
-    json_abi::{decode_function_response, encode_function_call},
+    contract::ABI_VERSION_2_4,
+    json_abi::{
+        add_sign_to_function_call, decode_function_response, decode_storage_fields,
+        decode_unknown_function_call, encode_function_call, encode_storage_fields,
+        prepare_function_call_for_sign, update_contract_data,
+    },
     Contract,
 };
 
-use crate::keyman::Keypair;
-use ever_block::{BuilderData, Result, SliceData};
+use crate::keyman::Signer;
+use ever_block::{BuilderData, Cell, HashmapE, Result, Serializable, SliceData};
+use serde_json::{Map, Value};
 
+/// Encodes the function call, the body of the external message is signed by the signer
+/// and the public key header is filled with its key if not specified.
 pub fn build_abi_body(
     abi_file: &str,
     method: &str,
     params: &str,
     header: Option<&str>,
-    keypair: Option<Keypair>,
+    signer: Option<&dyn Signer>,
     internal: bool,
     address: Option<String>,
 ) -> Result<BuilderData> {
-    encode_function_call(
-        &load_abi_json_string(abi_file)?,
+    let abi = load_abi_json_string(abi_file)?;
+    let signer = match signer {
+        Some(signer) if !internal => signer,
+        _ => {
+            return encode_function_call(
+                &abi,
+                method,
+                header,
+                params,
+                internal,
+                None,
+                address.as_deref(),
+            )
+        }
+    };
+    let public_key = signer.public_key()?;
+    let mut header: Map<String, Value> = match header {
+        Some(header) => serde_json::from_str(header)
+            .map_err(|e| format_err!("failed to parse ABI header: {}", e))?,
+        None => Map::new(),
+    };
+    header
+        .entry("pubkey")
+        .or_insert_with(|| Value::from(hex::encode(public_key)));
+    let (body, hash) = prepare_function_call_for_sign(
+        &abi,
         method,
-        header,
+        Some(&Value::Object(header).to_string()),
         params,
-        internal,
-        keypair.map(|p| p.private).as_ref(),
         address.as_deref(),
+    )?;
+    let signature = signer.sign(&hash)?;
+    add_sign_to_function_call(
+        &abi,
+        &signature,
+        Some(&public_key),
+        SliceData::load_builder(body)?,
     )
 }
 
@@ -63,3 +100,212 @@ pub fn decode_body(
         false,
     )
 }
+
+/// Decodes a call of any contract function, returns the function name and its parameters.
+pub fn decode_call(abi_file: &str, body: SliceData, internal: bool) -> Result<(String, String)> {
+    let decoded =
+        decode_unknown_function_call(&load_abi_json_string(abi_file)?, body, internal, false)?;
+    Ok((decoded.function_name, decoded.params))
+}
+
+/// Decodes the persistent data of the contract into the json described by the ABI `fields`
+/// section (including `_pubkey`, `_timestamp` and `_constructorFlag` if declared).
+pub fn decode_data(abi_file: &str, data: Option<&Cell>) -> Result<String> {
+    let data = SliceData::load_cell(data.cloned().unwrap_or_default())?;
+    decode_storage_fields(&load_abi_json_string(abi_file)?, data, false)
+        .map_err(|e| format_err!("cannot decode contract data: {}", e))
+}
+
+/// Builds the initial persistent data of the contract described by the ABI.
+/// Since ABI 2.4 data is encoded from the `fields` section, older versions
+/// use the data dictionary with an empty public key.
+pub fn build_initial_data(abi_file: &str) -> Result<Cell> {
+    let abi_json = load_abi_json_string(abi_file)?;
+    let contract = load_abi_contract(&abi_json)?;
+    if contract.version() >= &ABI_VERSION_2_4 {
+        encode_storage_fields(&abi_json, None)?.into_cell()
+    } else {
+        let data = SliceData::load_cell(HashmapE::with_bit_len(64).serialize()?)?;
+        Contract::insert_pubkey(data, &[0u8; 32])?.into_cell()
+    }
+}
+
+/// Sets the values of the contract data fields and the public key.
+/// Since ABI 2.4 the fields not present in `values` keep their current values,
+/// older versions update the static variables described in the `data` section.
+pub fn update_data(
+    abi_file: &str,
+    data: Option<Cell>,
+    values: Option<&str>,
+    pubkey: Option<[u8; 32]>,
+) -> Result<Cell> {
+    let abi_json = load_abi_json_string(abi_file)?;
+    let contract = load_abi_contract(&abi_json)?;
+    let values = match values {
+        Some(values) => serde_json::from_str::<Map<String, Value>>(values)
+            .map_err(|e| format_err!("data values must be a json object: {}", e))?,
+        None => Map::new(),
+    };
+    if contract.version() >= &ABI_VERSION_2_4 {
+        let mut fields = match data {
+            Some(data) => {
+                let decoded = decode_storage_fields(&abi_json, SliceData::load_cell(data)?, false)
+                    .map_err(|e| format_err!("cannot decode contract data: {}", e))?;
+                serde_json::from_str::<Map<String, Value>>(&decoded)?
+            }
+            None => Map::new(),
+        };
+        fields.extend(values);
+        if let Some(pubkey) = pubkey {
+            fields.insert(
+                "_pubkey".to_string(),
+                Value::String(format!("0x{}", hex::encode(pubkey))),
+            );
+        }
+        let fields = Value::Object(fields).to_string();
+        encode_storage_fields(&abi_json, Some(&fields))?.into_cell()
+    } else {
+        let data = match data {
+            Some(data) => SliceData::load_cell(data)?,
+            None => SliceData::load_cell(HashmapE::with_bit_len(64).serialize()?)?,
+        };
+        let mut data = if values.is_empty() {
+            data
+        } else {
+            let values = Value::Object(values).to_string();
+            update_contract_data(&abi_json, &values, data)?
+        };
+        if let Some(pubkey) = pubkey {
+            data = Contract::insert_pubkey(data, &pubkey)?;
+        }
+        Ok(data.into_cell())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIELDS_ABI: &str = r#"{
+        "ABI version": 2,
+        "version": "2.4",
+        "header": ["pubkey", "time", "expire"],
+        "functions": [],
+        "events": [],
+        "fields": [
+            {"name": "_pubkey", "type": "uint256", "init": true},
+            {"name": "_timestamp", "type": "uint64"},
+            {"name": "_constructorFlag", "type": "bool"},
+            {"name": "owner", "type": "uint32", "init": true},
+            {"name": "counter", "type": "uint64"}
+        ]
+    }"#;
+
+    const DATA_ABI: &str = r#"{
+        "ABI version": 2,
+        "version": "2.1",
+        "header": ["time"],
+        "functions": [],
+        "events": [],
+        "data": [
+            {"key": 1, "name": "owner", "type": "uint32"}
+        ]
+    }"#;
+
+    fn save_abi(name: &str, abi: &str) -> String {
+        let filename = std::env::temp_dir().join(format!(
+            "tvm_linker_test_{}_{}.abi.json",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&filename, abi).unwrap();
+        filename.to_str().unwrap().to_owned()
+    }
+
+    fn encode_fields(abi_file: &str, owner: u32, counter: u64) -> Cell {
+        let fields = serde_json::json!({
+            "_pubkey": format!("0x{}", "00".repeat(32)),
+            "_timestamp": 0,
+            "_constructorFlag": false,
+            "owner": owner,
+            "counter": counter,
+        });
+        let abi_json = load_abi_json_string(abi_file).unwrap();
+        encode_storage_fields(&abi_json, Some(&fields.to_string()))
+            .unwrap()
+            .into_cell()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_decode_data() {
+        let abi_file = save_abi("decode_data", FIELDS_ABI);
+        let data = encode_fields(&abi_file, 5, 7);
+        let decoded = decode_data(&abi_file, Some(&data)).unwrap();
+        let fields: Map<String, Value> = serde_json::from_str(&decoded).unwrap();
+        assert_eq!(fields.len(), 5);
+        // the decoded json is encoded back into the same data
+        let abi_json = load_abi_json_string(&abi_file).unwrap();
+        let encoded = encode_storage_fields(&abi_json, Some(&decoded)).unwrap();
+        assert_eq!(encoded.into_cell().unwrap(), data);
+        assert_ne!(
+            decoded,
+            decode_data(&abi_file, Some(&encode_fields(&abi_file, 5, 8))).unwrap()
+        );
+        std::fs::remove_file(abi_file).unwrap();
+    }
+
+    #[test]
+    fn test_decode_empty_data() {
+        let abi_file = save_abi("decode_empty_data", FIELDS_ABI);
+        let err = decode_data(&abi_file, None).unwrap_err();
+        assert!(err.to_string().starts_with("cannot decode contract data"));
+        let empty = Cell::default();
+        assert!(decode_data(&abi_file, Some(&empty)).is_err());
+        std::fs::remove_file(abi_file).unwrap();
+    }
+
+    #[test]
+    fn test_update_data_fields() {
+        let abi_file = save_abi("update_fields", FIELDS_ABI);
+        let data = encode_fields(&abi_file, 5, 7);
+
+        // the fields not present in values keep their values
+        let updated = update_data(&abi_file, Some(data), Some(r#"{"counter": 8}"#), None).unwrap();
+        assert_eq!(updated, encode_fields(&abi_file, 5, 8));
+
+        let pubkey = [0x11; 32];
+        let with_key = update_data(&abi_file, Some(updated), None, Some(pubkey)).unwrap();
+        let decoded = decode_data(&abi_file, Some(&with_key)).unwrap();
+        assert!(decoded.contains(&hex::encode(pubkey)));
+
+        // the data which can't be decoded is not replaced with the defaults
+        let invalid = BuilderData::with_raw(vec![0xAB], 8)
+            .unwrap()
+            .into_cell()
+            .unwrap();
+        assert!(update_data(&abi_file, Some(invalid), Some(r#"{"counter": 8}"#), None).is_err());
+        std::fs::remove_file(abi_file).unwrap();
+    }
+
+    #[test]
+    fn test_update_data_dictionary() {
+        let abi_file = save_abi("update_dictionary", DATA_ABI);
+        let pubkey = [0x22; 32];
+        let initial = build_initial_data(&abi_file).unwrap();
+        let with_key = update_data(&abi_file, Some(initial), None, Some(pubkey)).unwrap();
+        let stored =
+            Contract::get_pubkey(&SliceData::load_cell(with_key.clone()).unwrap()).unwrap();
+        assert_eq!(stored, Some(pubkey.to_vec()));
+
+        let updated =
+            update_data(&abi_file, Some(with_key), Some(r#"{"owner": 5}"#), None).unwrap();
+        assert_eq!(
+            updated,
+            update_data(&abi_file, None, Some(r#"{"owner": 5}"#), Some(pubkey)).unwrap()
+        );
+        let stored = Contract::get_pubkey(&SliceData::load_cell(updated).unwrap()).unwrap();
+        assert_eq!(stored, Some(pubkey.to_vec()));
+        std::fs::remove_file(abi_file).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::format_err;
+use ever_assembler::DbgInfo;
+use ever_block::{Cell, Result, Status};
+
+use crate::testcall::TraceEvent;
+
+/// Hit counts of source lines, the lines present in the debug map are
+/// reported with zero hits if they are never executed.
+#[derive(Default)]
+pub struct Coverage {
+    files: BTreeMap<String, BTreeMap<usize, u64>>,
+}
+
+impl Coverage {
+    pub fn new(code: Option<&Cell>, debug_info: Option<&DbgInfo>) -> Result<Self> {
+        let mut coverage = Coverage::default();
+        if let (Some(code), Some(debug_info)) = (code, debug_info) {
+            // debug map is keyed by hashes of code cells, so the code tree is walked
+            let mut visited = HashSet::new();
+            let mut cells = vec![code.clone()];
+            while let Some(cell) = cells.pop() {
+                if !visited.insert(cell.repr_hash()) {
+                    continue;
+                }
+                if let Some(offset_map) = debug_info.get(&cell.repr_hash()) {
+                    for pos in offset_map.values() {
+                        coverage.add(&pos.filename, pos.line, 0);
+                    }
+                }
+                for i in 0..cell.references_count() {
+                    cells.push(cell.reference(i)?);
+                }
+            }
+        }
+        Ok(coverage)
+    }
+
+    /// Creates coverage for the code lines of the debug map merged with the existing lcov file.
+    pub fn load(filename: &str, code: Option<&Cell>, debug_info: Option<&DbgInfo>) -> Result<Self> {
+        let mut coverage = Self::new(code, debug_info)?;
+        if std::path::Path::new(filename).exists() {
+            let lcov = std::fs::read_to_string(filename)
+                .map_err(|e| format_err!("failed to read coverage file {}: {}", filename, e))?;
+            coverage.merge_lcov(&lcov)?;
+        }
+        Ok(coverage)
+    }
+
+    fn add(&mut self, filename: &str, line: usize, hits: u64) {
+        *self
+            .files
+            .entry(filename.to_string())
+            .or_default()
+            .entry(line)
+            .or_default() += hits;
+    }
+
+    pub fn add_trace(&mut self, trace: &[TraceEvent]) {
+        for pos in trace.iter().filter_map(|event| event.position.as_ref()) {
+            self.add(&pos.filename, pos.line, 1);
+        }
+    }
+
+    /// Adds hit counts from `SF` and `DA` records of the lcov report.
+    pub fn merge_lcov(&mut self, lcov: &str) -> Status {
+        let mut filename = None;
+        for line in lcov.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix("SF:") {
+                filename = Some(name.to_string());
+            } else if let Some(data) = line.strip_prefix("DA:") {
+                let filename = filename
+                    .as_ref()
+                    .ok_or_else(|| format_err!("lcov record {} without source file", line))?;
+                let mut fields = data.split(',');
+                let (number, hits) = match (fields.next(), fields.next()) {
+                    (Some(number), Some(hits)) => (number.parse()?, hits.parse()?),
+                    _ => return Err(format_err!("invalid lcov record {}", line)),
+                };
+                self.add(filename, number, hits);
+            } else if line == "end_of_record" {
+                filename = None;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (filename, lines) in &self.files {
+            lcov += "TN:\n";
+            lcov += &format!("SF:{}\n", filename);
+            for (line, hits) in lines {
+                lcov += &format!("DA:{},{}\n", line, hits);
+            }
+            lcov += &format!("LF:{}\n", lines.len());
+            lcov += &format!("LH:{}\n", lines.values().filter(|hits| **hits > 0).count());
+            lcov += "end_of_record\n";
+        }
+        lcov
+    }
+
+    pub fn save(&self, filename: &str) -> Status {
+        std::fs::write(filename, self.to_lcov())
+            .map_err(|e| format_err!("failed to save coverage to {}: {}", filename, e))
+    }
+
+    pub fn summary(&self) -> String {
+        let total: usize = self.files.values().map(|lines| lines.len()).sum();
+        let hit: usize = self
+            .files
+            .values()
+            .map(|lines| lines.values().filter(|hits| **hits > 0).count())
+            .sum();
+        let percent = if total > 0 {
+            hit as f64 * 100.0 / total as f64
+        } else {
+            0.0
+        };
+        format!("Line coverage: {} of {} ({:.2}%)", hit, total, percent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ever_assembler::DbgPos;
+    use ever_block::UInt256;
+
+    fn event(filename: &str, line: usize) -> TraceEvent {
+        TraceEvent {
+            step: 0,
+            cmd_str: String::new(),
+            gas_used: 0,
+            gas_cmd: 0,
+            cell_hash: UInt256::default(),
+            offset: 0,
+            position: Some(DbgPos {
+                filename: filename.to_string(),
+                line,
+            }),
+            stack: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_debug_map_lines() {
+        let source = std::env::temp_dir().join(format!(
+            "tvm_linker_test_coverage_{}.code",
+            std::process::id()
+        ));
+        std::fs::write(&source, "PUSHINT 1\nPUSHINT 2\nADD\n").unwrap();
+        let source = source.to_str().unwrap();
+        let (code, debug_info) = crate::program::compile_code_with_libs(source, &[]).unwrap();
+        std::fs::remove_file(source).unwrap();
+
+        let coverage = Coverage::new(Some(&code), Some(&debug_info)).unwrap();
+        assert_eq!(coverage.files.len(), 1);
+        let lines = &coverage.files[source];
+        assert_eq!(lines.len(), 3);
+        assert!(lines.values().all(|hits| *hits == 0));
+
+        assert!(Coverage::new(None, Some(&debug_info))
+            .unwrap()
+            .files
+            .is_empty());
+    }
+
+    #[test]
+    fn test_lcov() {
+        let mut coverage = Coverage::default();
+        coverage.add("a.code", 3, 0);
+        coverage.add_trace(&[event("a.code", 1), event("a.code", 1), event("b.code", 7)]);
+        let lcov = coverage.to_lcov();
+        assert_eq!(
+            lcov,
+            "TN:\nSF:a.code\nDA:1,2\nDA:3,0\nLF:2\nLH:1\nend_of_record\n\
+             TN:\nSF:b.code\nDA:7,1\nLF:1\nLH:1\nend_of_record\n"
+        );
+        assert_eq!(coverage.summary(), "Line coverage: 2 of 3 (66.67%)");
+
+        let mut merged = Coverage::default();
+        merged.merge_lcov(&lcov).unwrap();
+        merged.merge_lcov(&lcov).unwrap();
+        assert_eq!(merged.files["a.code"][&1], 4);
+        assert_eq!(merged.files["a.code"][&3], 0);
+        assert!(merged.merge_lcov("DA:1,1\n").is_err());
+    }
+}
@@ -0,0 +1,687 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::{
+    io::{BufRead, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, format_err};
+use ever_assembler::DbgPos;
+use ever_block::{MsgAddressInt, Result, SliceData, Status};
+use ever_vm::executor::{Engine, EngineTraceInfo};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::abi::build_abi_body;
+use crate::debugger::{Breakpoint, Mode};
+use crate::keyman::load_signer;
+use crate::printer::execution_result_printer;
+use crate::program::{decode_hex_string, load_from_file, parse_address};
+use crate::testcall::{
+    call_contract, find_position, load_config, load_debug_info, MsgInfo, TestCallParams,
+};
+
+const THREAD_ID: u64 = 1;
+const FRAME_ID: u64 = 1;
+const STACK_REFERENCE: u64 = 1;
+const REGISTERS_REFERENCE: u64 = 2;
+
+/// Arguments of the `launch` request describing the contract call.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchArgs {
+    /// Contract tvc file
+    pub program: String,
+    pub debug_map: Option<String>,
+    pub address: Option<String>,
+    pub balance: Option<String>,
+    pub config: Option<String>,
+    pub now: Option<u32>,
+    /// Value of the internal message, external message is sent if not specified
+    pub internal: Option<String>,
+    pub src: Option<String>,
+    pub abi: Option<String>,
+    pub method: Option<String>,
+    pub params: Option<Value>,
+    pub header: Option<Value>,
+    /// Key file (or `cmd:<command>` signer) to sign the external message body
+    pub sign: Option<String>,
+    /// Message body as a bitstring or a hex string, used instead of the ABI call
+    pub body: Option<String>,
+    #[serde(default)]
+    pub stop_on_entry: bool,
+}
+
+impl LaunchArgs {
+    fn build_body(&self, address: &MsgAddressInt) -> Result<Option<SliceData>> {
+        if let Some(body) = &self.body {
+            let (buf, bits) = decode_hex_string(body.clone())?;
+            return Ok(Some(SliceData::from_raw(buf, bits)));
+        }
+        let (abi, method) = match (&self.abi, &self.method) {
+            (Some(abi), Some(method)) => (abi, method),
+            (None, None) => return Ok(None),
+            _ => bail!("both abi and method must be supplied"),
+        };
+        let params = self
+            .params
+            .as_ref()
+            .map_or("{}".to_string(), |p| p.to_string());
+        let header = self.header.as_ref().map(|header| header.to_string());
+        let signer = self.sign.as_deref().map(load_signer).transpose()?;
+        let body = build_abi_body(
+            abi,
+            method,
+            &params,
+            header.as_deref(),
+            signer.as_deref(),
+            self.internal.is_some(),
+            Some(address.to_string()),
+        )?;
+        Ok(Some(SliceData::load_builder(body)?))
+    }
+}
+
+enum Action {
+    None,
+    Launch(Box<LaunchArgs>),
+    Run,
+    Resume,
+    Disconnect,
+}
+
+/// State of the stopped execution shown to the client
+struct Frame {
+    cmd_str: String,
+    position: Option<DbgPos>,
+    stack: Vec<String>,
+    registers: Vec<(String, String)>,
+}
+
+/// Reads one message framed with the `Content-Length` header, returns None at the end of input.
+pub fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let mut content = vec![0; length.unwrap()];
+    reader.read_exact(&mut content)?;
+    Ok(Some(serde_json::from_slice(&content)?))
+}
+
+pub fn write_message<W: Write>(writer: &mut W, message: &Value) -> Status {
+    let content = message.to_string();
+    write!(
+        writer,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+struct Session<R, W> {
+    reader: R,
+    writer: W,
+    seq: u64,
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    frame: Option<Frame>,
+    disconnected: bool,
+}
+
+impl<R: BufRead, W: Write> Session<R, W> {
+    fn send(&mut self, mut message: Value) -> Status {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.writer, &message)
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) -> Status {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }))
+    }
+
+    fn respond_error(&mut self, request: &Value, message: String) -> Status {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }))
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Status {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn output(&mut self, output: String) -> Status {
+        self.event("output", json!({ "category": "console", "output": output }))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let path = args["source"]["path"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        self.breakpoints
+            .retain(|bp| !matches!(bp, Breakpoint::Line { filename, .. } if *filename == path));
+        let lines: Vec<usize> = args["breakpoints"]
+            .as_array()
+            .map(|breakpoints| {
+                breakpoints
+                    .iter()
+                    .filter_map(|bp| bp["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect()
+            })
+            .unwrap_or_default();
+        for line in &lines {
+            self.breakpoints.push(Breakpoint::Line {
+                filename: path.clone(),
+                line: *line,
+            });
+        }
+        let breakpoints: Vec<Value> = lines
+            .iter()
+            .map(|line| json!({ "verified": true, "line": line }))
+            .collect();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    fn stack_trace(&self) -> Value {
+        let frames: Vec<Value> = self
+            .frame
+            .iter()
+            .map(|frame| match &frame.position {
+                Some(pos) => json!({
+                    "id": FRAME_ID,
+                    "name": frame.cmd_str,
+                    "line": pos.line,
+                    "column": 1,
+                    "source": {
+                        "name": pos.filename.rsplit('/').next(),
+                        "path": pos.filename,
+                    },
+                }),
+                None => json!({ "id": FRAME_ID, "name": frame.cmd_str, "line": 0, "column": 0 }),
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, reference: u64) -> Value {
+        let variables: Vec<Value> = match (&self.frame, reference) {
+            (Some(frame), STACK_REFERENCE) => frame
+                .stack
+                .iter()
+                .enumerate()
+                .map(|(index, item)| {
+                    json!({ "name": format!("s{}", index), "value": item, "variablesReference": 0 })
+                })
+                .collect(),
+            (Some(frame), REGISTERS_REFERENCE) => frame
+                .registers
+                .iter()
+                .map(|(name, value)| {
+                    json!({ "name": name, "value": value, "variablesReference": 0 })
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        json!({ "variables": variables })
+    }
+
+    /// Reads and handles the next request.
+    fn process_next(&mut self) -> Result<Action> {
+        let request = match read_message(&mut self.reader)? {
+            Some(request) => request,
+            None => {
+                self.mode = Mode::Run;
+                self.disconnected = true;
+                return Ok(Action::Disconnect);
+            }
+        };
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let mut action = Action::None;
+        match command.as_str() {
+            "initialize" => {
+                self.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsSingleThreadExecutionRequests": true,
+                    }),
+                )?;
+                self.event("initialized", json!({}))?;
+                return Ok(action);
+            }
+            "launch" => match serde_json::from_value::<LaunchArgs>(args.clone()) {
+                Ok(launch) => action = Action::Launch(Box::new(launch)),
+                Err(err) => {
+                    self.respond_error(&request, format!("invalid launch arguments: {}", err))?;
+                    return Ok(action);
+                }
+            },
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(args);
+                self.respond(&request, body)?;
+                return Ok(action);
+            }
+            "configurationDone" => action = Action::Run,
+            "threads" => {
+                let body = json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] });
+                self.respond(&request, body)?;
+                return Ok(action);
+            }
+            "stackTrace" => {
+                let body = self.stack_trace();
+                self.respond(&request, body)?;
+                return Ok(action);
+            }
+            "scopes" => {
+                let scopes = [
+                    ("Stack", STACK_REFERENCE),
+                    ("Registers", REGISTERS_REFERENCE),
+                ];
+                let scopes: Vec<Value> = scopes
+                    .iter()
+                    .map(|(name, reference)| {
+                        json!({ "name": name, "variablesReference": reference, "expensive": false })
+                    })
+                    .collect();
+                let body = json!({ "scopes": scopes });
+                self.respond(&request, body)?;
+                return Ok(action);
+            }
+            "variables" => {
+                let body = self.variables(args["variablesReference"].as_u64().unwrap_or_default());
+                self.respond(&request, body)?;
+                return Ok(action);
+            }
+            "continue" => {
+                self.mode = Mode::Continue;
+                action = Action::Resume;
+            }
+            "next" => {
+                let position = self.frame.as_ref().and_then(|frame| frame.position.clone());
+                self.mode = Mode::Next(position);
+                action = Action::Resume;
+            }
+            "disconnect" | "terminate" => {
+                self.mode = Mode::Run;
+                self.disconnected = true;
+                action = Action::Disconnect;
+            }
+            "setExceptionBreakpoints" => (),
+            "stepIn" | "stepOut" => {
+                self.respond_error(&request, format!("{} is not supported, use next", command))?;
+                return Ok(action);
+            }
+            _ => {
+                self.respond_error(&request, format!("unsupported request {}", command))?;
+                return Ok(action);
+            }
+        }
+        let body = match command.as_str() {
+            "continue" => json!({ "allThreadsContinued": true }),
+            _ => json!({}),
+        };
+        self.respond(&request, body)?;
+        Ok(action)
+    }
+
+    fn on_step(
+        &mut self,
+        engine: &Engine,
+        info: &EngineTraceInfo,
+        position: Option<DbgPos>,
+    ) -> Status {
+        let reason = match self
+            .mode
+            .stop_reason(&self.breakpoints, info, position.as_ref())
+        {
+            Some(reason) => reason,
+            None => return Ok(()),
+        };
+        let mut registers = vec![
+            ("gas used".to_string(), info.gas_used.to_string()),
+            (
+                "gas (last instruction)".to_string(),
+                info.gas_cmd.to_string(),
+            ),
+        ];
+        for (index, line) in engine.dump_ctrls(false).lines().enumerate() {
+            match line.split_once([':', '=']) {
+                Some((name, value)) => {
+                    registers.push((name.trim().to_string(), value.trim().to_string()))
+                }
+                None => registers.push((index.to_string(), line.to_string())),
+            }
+        }
+        self.frame = Some(Frame {
+            cmd_str: info.cmd_str.clone(),
+            position,
+            stack: info
+                .stack
+                .iter()
+                .rev()
+                .map(|item| item.to_string())
+                .collect(),
+            registers,
+        });
+        self.event(
+            "stopped",
+            json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+        )?;
+        loop {
+            match self.process_next()? {
+                Action::Resume | Action::Disconnect => break,
+                _ => (),
+            }
+        }
+        self.frame = None;
+        Ok(())
+    }
+}
+
+fn launch<R, W>(session: &Arc<Mutex<Session<R, W>>>, args: &LaunchArgs) -> Status
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let address = args.address.clone().unwrap_or_else(|| "0".repeat(64));
+    let address = parse_address(&address)?;
+    let state_init = load_from_file(&args.program)?;
+    let body = args.build_body(&address)?;
+    let debug_info = args.debug_map.as_deref().and_then(load_debug_info);
+    let hook_debug_info = debug_info.clone();
+
+    session.lock().unwrap().mode = match args.stop_on_entry {
+        true => Mode::Step,
+        false => Mode::Continue,
+    };
+    let hook_session = session.clone();
+    let mut params = TestCallParams::new(MsgInfo {
+        balance: args.internal.as_deref(),
+        src: args.src.as_deref(),
+        body,
+        ..Default::default()
+    })
+    .with_debug_info(debug_info)
+    .with_trace_hook(Arc::new(move |engine, info| {
+        let position = find_position(info, &hook_debug_info);
+        let mut session = hook_session.lock().unwrap();
+        if let Err(err) = session.on_step(engine, info, position) {
+            session.mode = Mode::Run;
+            log::error!("debug session failed: {}", err);
+        }
+    }));
+    if let Some(now) = args.now {
+        params.msg_info.now = now;
+    }
+    if let Some(balance) = &args.balance {
+        params = params.with_balance(balance);
+    }
+    if let Some(config) = args.config.as_deref().and_then(load_config) {
+        params = params.with_config(config);
+    }
+    if args.body.is_some() && args.internal.is_none() {
+        params = params.with_key_file(args.sign.as_deref());
+    }
+
+    let result = call_contract(address, state_init, params)?;
+    let mut session = session.lock().unwrap();
+    session.output(execution_result_printer(&result))?;
+    session.event("exited", json!({ "exitCode": result.exit_code }))?;
+    Ok(())
+}
+
+/// Runs the debug adapter reading requests from the reader and writing responses
+/// and events to the writer until the client disconnects.
+pub fn run<R, W>(reader: R, writer: W) -> Result<W>
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    let session = Arc::new(Mutex::new(Session {
+        reader,
+        writer,
+        seq: 0,
+        breakpoints: Vec::new(),
+        mode: Mode::Continue,
+        frame: None,
+        disconnected: false,
+    }));
+    let mut launch_args = None;
+    loop {
+        let action = session.lock().unwrap().process_next()?;
+        match action {
+            Action::Launch(args) => launch_args = Some(args),
+            Action::Run => {
+                if let Some(args) = launch_args.take() {
+                    if let Err(err) = launch(&session, &args) {
+                        session
+                            .lock()
+                            .unwrap()
+                            .output(format!("Error: {}\n", err))?;
+                    }
+                    let mut session = session.lock().unwrap();
+                    session.event("terminated", json!({}))?;
+                    if session.disconnected {
+                        break;
+                    }
+                }
+            }
+            Action::Disconnect => break,
+            Action::None | Action::Resume => (),
+        }
+    }
+    let session = Arc::try_unwrap(session)
+        .map_err(|_| format_err!("debug session is still in use"))?
+        .into_inner()
+        .map_err(|_| format_err!("debug session is poisoned"))?;
+    Ok(session.writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn script(requests: &[Value]) -> Cursor<Vec<u8>> {
+        let mut buffer = Vec::new();
+        for request in requests {
+            write_message(&mut buffer, request).unwrap();
+        }
+        Cursor::new(buffer)
+    }
+
+    fn read_all(output: Vec<u8>) -> Vec<Value> {
+        let mut reader = Cursor::new(output);
+        let mut messages = Vec::new();
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[test]
+    fn test_message_framing() {
+        let message = json!({ "seq": 1, "type": "request", "command": "threads" });
+        let mut reader = script(&[message.clone(), message.clone()]);
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message.clone()));
+        assert_eq!(read_message(&mut reader).unwrap(), Some(message));
+        assert_eq!(read_message(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_debug_session() {
+        let dir = std::env::temp_dir();
+        let name = |ext: &str| {
+            let path = dir.join(format!(
+                "tvm_linker_test_dap_{}.{}",
+                std::process::id(),
+                ext
+            ));
+            path.to_str().unwrap().to_string()
+        };
+        let (source, tvc, debug_map) = (name("code"), name("tvc"), name("debug.json"));
+        std::fs::write(
+            &source,
+            "PUSHINT 1\nPUSHINT 2\nADD\nPUSHINT 3\nDROP\nDROP\n",
+        )
+        .unwrap();
+        let (code, debug_info) = crate::program::compile_code_with_libs(&source, &[]).unwrap();
+        let state_init = ever_block::StateInit {
+            code: Some(code),
+            data: Some(Default::default()),
+            ..Default::default()
+        };
+        crate::program::save_to_file(state_init, Some(&tvc), 0, true).unwrap();
+        crate::program::save_debug_info(&debug_info, &debug_map).unwrap();
+
+        let reader = script(&[
+            json!({ "seq": 1, "type": "request", "command": "initialize", "arguments": {} }),
+            json!({ "seq": 2, "type": "request", "command": "setBreakpoints", "arguments": {
+                "source": { "path": source },
+                "breakpoints": [{ "line": 4 }],
+            }}),
+            json!({ "seq": 3, "type": "request", "command": "launch", "arguments": {
+                "program": tvc,
+                "debugMap": debug_map,
+                "now": 1,
+            }}),
+            json!({ "seq": 4, "type": "request", "command": "configurationDone" }),
+            json!({ "seq": 5, "type": "request", "command": "stackTrace", "arguments": {} }),
+            json!({ "seq": 6, "type": "request", "command": "variables", "arguments": {
+                "variablesReference": STACK_REFERENCE,
+            }}),
+            json!({ "seq": 7, "type": "request", "command": "next", "arguments": {} }),
+            json!({ "seq": 8, "type": "request", "command": "stackTrace", "arguments": {} }),
+            json!({ "seq": 9, "type": "request", "command": "continue", "arguments": {} }),
+            json!({ "seq": 10, "type": "request", "command": "disconnect" }),
+        ]);
+        let messages = read_all(run(reader, Vec::new()).unwrap());
+        for file in [&source, &tvc, &debug_map] {
+            std::fs::remove_file(file).unwrap();
+        }
+
+        let names: Vec<_> = messages
+            .iter()
+            .map(|m| m["command"].as_str().or(m["event"].as_str()).unwrap())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "initialize",
+                "initialized",
+                "setBreakpoints",
+                "launch",
+                "configurationDone",
+                "stopped",
+                "stackTrace",
+                "variables",
+                "next",
+                "stopped",
+                "stackTrace",
+                "continue",
+                "output",
+                "exited",
+                "terminated",
+                "disconnect",
+            ]
+        );
+        assert!(messages
+            .iter()
+            .filter(|m| m["type"] == "response")
+            .all(|m| m["success"] == true));
+        // stops on the breakpoint before PUSHINT 3 with the sum on the top of the stack
+        assert_eq!(messages[5]["body"]["reason"], "breakpoint");
+        let frame = &messages[6]["body"]["stackFrames"][0];
+        assert_eq!(frame["line"], 4);
+        assert_eq!(frame["source"]["path"], source.as_str());
+        assert_eq!(messages[7]["body"]["variables"][0]["value"], "3");
+        // next stops on the following source line
+        assert_eq!(messages[9]["body"]["reason"], "step");
+        assert_eq!(messages[10]["body"]["stackFrames"][0]["line"], 5);
+        assert_eq!(messages[13]["body"]["exitCode"], 0);
+    }
+
+    #[test]
+    fn test_session() {
+        let reader = script(&[
+            json!({ "seq": 1, "type": "request", "command": "initialize", "arguments": {} }),
+            json!({ "seq": 2, "type": "request", "command": "setBreakpoints", "arguments": {
+                "source": { "path": "/work/contract.code" },
+                "breakpoints": [{ "line": 10 }, { "line": 12 }],
+            }}),
+            json!({ "seq": 3, "type": "request", "command": "launch", "arguments": {} }),
+            json!({ "seq": 4, "type": "request", "command": "launch", "arguments": {
+                "program": "not_existing.tvc",
+            }}),
+            json!({ "seq": 5, "type": "request", "command": "configurationDone" }),
+            json!({ "seq": 6, "type": "request", "command": "stepIn" }),
+            json!({ "seq": 7, "type": "request", "command": "disconnect" }),
+        ]);
+        let messages = read_all(run(reader, Vec::new()).unwrap());
+        let commands: Vec<_> = messages
+            .iter()
+            .map(|m| {
+                let name = m["command"].as_str().or(m["event"].as_str()).unwrap();
+                (name.to_string(), m["success"].as_bool())
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            vec![
+                ("initialize".to_string(), Some(true)),
+                ("initialized".to_string(), None),
+                ("setBreakpoints".to_string(), Some(true)),
+                ("launch".to_string(), Some(false)),
+                ("launch".to_string(), Some(true)),
+                ("configurationDone".to_string(), Some(true)),
+                ("output".to_string(), None),
+                ("terminated".to_string(), None),
+                ("stepIn".to_string(), Some(false)),
+                ("disconnect".to_string(), Some(true)),
+            ]
+        );
+        assert_eq!(messages[2]["body"]["breakpoints"][1]["line"], 12);
+        assert!(messages[6]["body"]["output"]
+            .as_str()
+            .unwrap()
+            .starts_with("Error:"));
+    }
+}
@@ -0,0 +1,458 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Stdin, Stdout, Write},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{bail, format_err};
+use ever_assembler::{DbgInfo, DbgPos};
+use ever_block::{Result, Status};
+use ever_vm::executor::{Engine, EngineTraceInfo};
+
+use crate::testcall::{find_position, TraceHook};
+
+const SOURCE_CONTEXT_LINES: usize = 3;
+
+const HELP: &str = "\
+Commands:
+  s, step                   execute one instruction
+  n, next                   run to the next source line
+  c, continue               run to the next breakpoint
+  b, break <file:line|INSN> set breakpoint on the source line or instruction name
+  d, delete <N>             delete breakpoint by number
+  i, info                   list breakpoints
+  p, print [N]              print the stack or the stack item N (0 is the top)
+  r, regs                   print control registers (c4, c5, c7 etc.)
+  g, gas                    print used gas
+  l, list                   print the source lines around the current position
+  q, quit                   run to the end without stopping
+  h, help                   print this help";
+
+#[derive(Debug, PartialEq)]
+pub enum Breakpoint {
+    Line { filename: String, line: usize },
+    Instruction(String),
+}
+
+impl Breakpoint {
+    pub fn parse(arg: &str) -> Result<Self> {
+        if let Some((filename, line)) = arg.rsplit_once(':') {
+            let line = line
+                .parse::<usize>()
+                .map_err(|e| format_err!("invalid line number {}: {}", line, e))?;
+            return Ok(Breakpoint::Line {
+                filename: filename.to_string(),
+                line,
+            });
+        }
+        if arg.is_empty() {
+            bail!("breakpoint location is not specified");
+        }
+        Ok(Breakpoint::Instruction(arg.to_uppercase()))
+    }
+
+    pub fn matches(&self, cmd_str: &str, position: Option<&DbgPos>) -> bool {
+        match self {
+            Breakpoint::Line { filename, line } => position.map_or(false, |pos| {
+                pos.line == *line && same_file(filename, &pos.filename)
+            }),
+            Breakpoint::Instruction(name) => cmd_str
+                .split_whitespace()
+                .next()
+                .map_or(false, |insn| insn == name),
+        }
+    }
+}
+
+/// Compares the file paths, one of them can be relative to the directory of the other.
+pub fn same_file(path: &str, filename: &str) -> bool {
+    path == filename
+        || path.ends_with(&format!("/{}", filename))
+        || filename.ends_with(&format!("/{}", path))
+}
+
+impl std::fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Breakpoint::Line { filename, line } => write!(f, "{}:{}", filename, line),
+            Breakpoint::Instruction(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Step,
+    Next,
+    Continue,
+    Break(Breakpoint),
+    Delete(usize),
+    Info,
+    Print(Option<usize>),
+    Regs,
+    Gas,
+    List,
+    Quit,
+    Help,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("step");
+        let arg = words.next();
+        Ok(match command {
+            "s" | "step" => Command::Step,
+            "n" | "next" => Command::Next,
+            "c" | "continue" => Command::Continue,
+            "b" | "break" => Command::Break(Breakpoint::parse(arg.unwrap_or_default())?),
+            "d" | "delete" => Command::Delete(
+                arg.ok_or_else(|| format_err!("breakpoint number is not specified"))?
+                    .parse()?,
+            ),
+            "i" | "info" => Command::Info,
+            "p" | "print" => Command::Print(arg.map(|index| index.parse()).transpose()?),
+            "r" | "regs" => Command::Regs,
+            "g" | "gas" => Command::Gas,
+            "l" | "list" => Command::List,
+            "q" | "quit" => Command::Quit,
+            "h" | "help" => Command::Help,
+            _ => bail!("unknown command {}, type h for help", command),
+        })
+    }
+}
+
+/// Stepping mode of the debugger and the debug adapter session.
+pub enum Mode {
+    Step,
+    Next(Option<DbgPos>),
+    Continue,
+    Run,
+}
+
+impl Mode {
+    /// Returns the reason to stop before the instruction: `entry`, `step` or `breakpoint`.
+    pub fn stop_reason(
+        &self,
+        breakpoints: &[Breakpoint],
+        info: &EngineTraceInfo,
+        position: Option<&DbgPos>,
+    ) -> Option<&'static str> {
+        let breakpoint = breakpoints
+            .iter()
+            .any(|bp| bp.matches(&info.cmd_str, position));
+        match self {
+            Mode::Step => Some(if info.step == 0 { "entry" } else { "step" }),
+            Mode::Next(_) | Mode::Continue if breakpoint => Some("breakpoint"),
+            Mode::Next(from) if position.is_some() && position != from.as_ref() => Some("step"),
+            _ => None,
+        }
+    }
+}
+
+/// Interactive debugger stopping the execution on breakpoints and reading
+/// commands from the reader (stdin by default).
+pub struct Debugger<R, W> {
+    reader: R,
+    writer: W,
+    debug_info: Option<DbgInfo>,
+    breakpoints: Vec<Breakpoint>,
+    mode: Mode,
+    sources: HashMap<String, Vec<String>>,
+}
+
+impl Debugger<BufReader<Stdin>, Stdout> {
+    pub fn new(debug_info: Option<DbgInfo>) -> Self {
+        Self::with_io(
+            debug_info,
+            BufReader::new(std::io::stdin()),
+            std::io::stdout(),
+        )
+    }
+}
+
+impl<R, W> Debugger<R, W>
+where
+    R: BufRead + Send + 'static,
+    W: Write + Send + 'static,
+{
+    pub fn with_io(debug_info: Option<DbgInfo>, reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            debug_info,
+            breakpoints: Vec::new(),
+            mode: Mode::Step,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Wraps the debugger into the hook for `TestCallParams::with_trace_hook`.
+    pub fn into_hook(self) -> TraceHook {
+        let debugger = Mutex::new(self);
+        Arc::new(move |engine, info| {
+            let mut debugger = debugger.lock().unwrap();
+            if let Err(err) = debugger.on_step(engine, info) {
+                debugger.mode = Mode::Run;
+                log::error!("debugger failed: {}", err);
+            }
+        })
+    }
+
+    fn on_step(&mut self, engine: &Engine, info: &EngineTraceInfo) -> Status {
+        let position = find_position(info, &self.debug_info);
+        let reason = self
+            .mode
+            .stop_reason(&self.breakpoints, info, position.as_ref());
+        if reason.is_none() {
+            return Ok(());
+        }
+        write!(self.writer, "{}: {}", info.step, info.cmd_str)?;
+        if let Some(pos) = &position {
+            write!(self.writer, " at {}:{}", pos.filename, pos.line)?;
+        }
+        writeln!(self.writer)?;
+
+        loop {
+            write!(self.writer, "(debug) ")?;
+            self.writer.flush()?;
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                self.mode = Mode::Run;
+                return Ok(());
+            }
+            let command = match Command::parse(&line) {
+                Ok(command) => command,
+                Err(err) => {
+                    writeln!(self.writer, "{}", err)?;
+                    continue;
+                }
+            };
+            match command {
+                Command::Step => self.mode = Mode::Step,
+                Command::Next => self.mode = Mode::Next(position.clone()),
+                Command::Continue => self.mode = Mode::Continue,
+                Command::Quit => self.mode = Mode::Run,
+                Command::Break(breakpoint) => {
+                    writeln!(
+                        self.writer,
+                        "Breakpoint {} at {}",
+                        self.breakpoints.len(),
+                        breakpoint
+                    )?;
+                    self.breakpoints.push(breakpoint);
+                    continue;
+                }
+                Command::Delete(index) if index < self.breakpoints.len() => {
+                    let breakpoint = self.breakpoints.remove(index);
+                    writeln!(self.writer, "Deleted breakpoint {}", breakpoint)?;
+                    continue;
+                }
+                Command::Delete(index) => {
+                    writeln!(self.writer, "No breakpoint {}", index)?;
+                    continue;
+                }
+                Command::Info => {
+                    for (index, breakpoint) in self.breakpoints.iter().enumerate() {
+                        writeln!(self.writer, "{}: {}", index, breakpoint)?;
+                    }
+                    continue;
+                }
+                Command::Print(None) => {
+                    for (index, item) in info.stack.iter().rev().enumerate() {
+                        writeln!(self.writer, "{}: {}", index, item)?;
+                    }
+                    continue;
+                }
+                Command::Print(Some(index)) => {
+                    match info.stack.iter().rev().nth(index) {
+                        Some(item) => writeln!(self.writer, "{}: {}", index, item)?,
+                        None => writeln!(self.writer, "Stack depth is {}", info.stack.depth())?,
+                    }
+                    continue;
+                }
+                Command::Regs => {
+                    writeln!(self.writer, "{}", engine.dump_ctrls(false))?;
+                    continue;
+                }
+                Command::Gas => {
+                    writeln!(
+                        self.writer,
+                        "Gas used: {} (last instruction {})",
+                        info.gas_used, info.gas_cmd
+                    )?;
+                    continue;
+                }
+                Command::List => {
+                    match &position {
+                        Some(pos) => {
+                            let lines = self.source_lines(pos);
+                            write!(self.writer, "{}", lines)?
+                        }
+                        None => writeln!(self.writer, "Source position is unknown")?,
+                    }
+                    continue;
+                }
+                Command::Help => {
+                    writeln!(self.writer, "{}", HELP)?;
+                    continue;
+                }
+            }
+            return Ok(());
+        }
+    }
+    fn source_lines(&mut self, pos: &DbgPos) -> String {
+        let lines = self.sources.entry(pos.filename.clone()).or_insert_with(|| {
+            std::fs::read_to_string(&pos.filename)
+                .map(|source| source.lines().map(|line| line.to_string()).collect())
+                .unwrap_or_default()
+        });
+        let first = pos.line.saturating_sub(SOURCE_CONTEXT_LINES).max(1);
+        let last = (pos.line + SOURCE_CONTEXT_LINES).min(lines.len());
+        (first..=last)
+            .map(|number| {
+                let marker = if number == pos.line { "=>" } else { "  " };
+                format!("{} {:>5} {}\n", marker, number, lines[number - 1])
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::compile_code_with_libs;
+    use crate::testcall::{call_contract, MsgInfo, TestCallParams};
+    use ever_block::{MsgAddressInt, StateInit};
+    use std::io::Cursor;
+
+    /// Writer shared with the test to read the output of the debugger owned by the hook.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_debug_session() {
+        let source = std::env::temp_dir().join(format!(
+            "tvm_linker_test_debugger_{}.code",
+            std::process::id()
+        ));
+        std::fs::write(
+            &source,
+            "PUSHINT 1\nPUSHINT 2\nADD\nPUSHINT 3\nDROP\nDROP\n",
+        )
+        .unwrap();
+        let source = source.to_str().unwrap();
+        let (code, debug_info) = compile_code_with_libs(source, &[]).unwrap();
+        std::fs::remove_file(source).unwrap();
+
+        let commands = format!("b drop\ns\nb {}:4\nc\nc\nd 0\nc\n", source);
+        let output = Output::default();
+        let debugger = Debugger::with_io(Some(debug_info), Cursor::new(commands), output.clone());
+        let state_init = StateInit {
+            code: Some(code),
+            data: Some(Default::default()),
+            ..Default::default()
+        };
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let params = TestCallParams::new(MsgInfo {
+            now: 1,
+            ..Default::default()
+        })
+        .with_trace_hook(debugger.into_hook());
+        let result = call_contract(addr, state_init, params).unwrap();
+        assert!(result.is_vm_success);
+
+        let output = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        // the execution stops on entry, after the step, on the line and instruction breakpoints
+        let stops: Vec<String> = output
+            .lines()
+            .map(|line| line.trim_start_matches("(debug) "))
+            .filter(|line| line.split(':').next().unwrap().parse::<u32>().is_ok())
+            .map(|line| {
+                let (insn, pos) = line.split_once(':').unwrap().1.split_once(" at ").unwrap();
+                let line = pos.rsplit(':').next().unwrap();
+                format!("{} {}", insn.split_whitespace().next().unwrap(), line)
+            })
+            .collect();
+        assert_eq!(stops, vec!["PUSHINT 1", "PUSHINT 2", "PUSHINT 4", "DROP 5"]);
+        assert!(output.contains("Breakpoint 1 at "));
+        assert!(output.contains("Deleted breakpoint DROP"));
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(Command::parse("\n").unwrap(), Command::Step);
+        assert_eq!(Command::parse("c").unwrap(), Command::Continue);
+        assert_eq!(Command::parse("p 2").unwrap(), Command::Print(Some(2)));
+        assert_eq!(
+            Command::parse("b contract.code:42").unwrap(),
+            Command::Break(Breakpoint::Line {
+                filename: "contract.code".to_string(),
+                line: 42
+            })
+        );
+        assert_eq!(
+            Command::parse("break sendrawmsg").unwrap(),
+            Command::Break(Breakpoint::Instruction("SENDRAWMSG".to_string()))
+        );
+        assert!(Command::parse("b").is_err());
+        assert!(Command::parse("d").is_err());
+        assert!(Command::parse("jump").is_err());
+    }
+
+    #[test]
+    fn test_breakpoint_matches() {
+        let pos = DbgPos {
+            filename: "/src/contract.code".to_string(),
+            line: 42,
+        };
+        assert!(Breakpoint::parse("contract.code:42")
+            .unwrap()
+            .matches("ADD", Some(&pos)));
+        assert!(!Breakpoint::parse("contract.code:43")
+            .unwrap()
+            .matches("ADD", Some(&pos)));
+        assert!(!Breakpoint::parse("contract.code:42")
+            .unwrap()
+            .matches("ADD", None));
+        assert!(Breakpoint::parse("PUSHINT")
+            .unwrap()
+            .matches("PUSHINT 5", None));
+        assert!(!Breakpoint::parse("PUSH")
+            .unwrap()
+            .matches("PUSHINT 5", None));
+        assert!(!Breakpoint::parse("tract.code:42")
+            .unwrap()
+            .matches("ADD", Some(&pos)));
+    }
+
+    #[test]
+    fn test_same_file() {
+        assert!(same_file("/work/contract.code", "contract.code"));
+        assert!(same_file("contract.code", "/work/contract.code"));
+        assert!(same_file("contract.code", "contract.code"));
+        assert!(!same_file("/work/other.code", "contract.code"));
+    }
+}
@@ -10,9 +10,39 @@
  * See the License for the specific EVERX DEV software governing permissions and
  * limitations under the License.
  */
-use anyhow::format_err;
-use ever_block::{ed25519_create_private_key, Ed25519PrivateKey, Ed25519PublicKey, Result};
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{bail, format_err};
+use bip39::{Language, Mnemonic};
+use ever_block::{
+    ed25519_create_private_key, ed25519_sign_with_secret, Ed25519PrivateKey, Ed25519PublicKey,
+    Result, Status,
+};
+use hmac::{Hmac, Mac};
 use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256, Sha512};
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Derivation path of the default wallet key in the ecosystem wallets
+pub const DEFAULT_HD_PATH: &str = "m/44'/396'/0'/0/0";
+pub const DEFAULT_WORD_COUNT: usize = 12;
+
+/// Prefix of the `--sign` value selecting the external command signer
+pub const COMMAND_SIGNER_PREFIX: &str = "cmd:";
+/// Environment variable with the password of encrypted key files
+pub const PASSWORD_ENV: &str = "TVM_LINKER_KEY_PASSWORD";
+
+const HARDENED_INDEX: u32 = 0x80000000;
+const ENCRYPTED_FIELD: &str = "encrypted";
+const NONCE_LEN: usize = 12;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Cleared when stdin is used for other purposes (e.g. the DAP transport)
+static PASSWORD_PROMPT: AtomicBool = AtomicBool::new(true);
 
 pub struct Keypair {
     pub private: Ed25519PrivateKey,
@@ -20,15 +50,40 @@ pub struct Keypair {
 }
 
 impl Keypair {
+    /// Loads the keypair from the hex json `{"public": .., "secret": ..}`, the password
+    /// encrypted json, the raw 32-byte secret or 64-byte secret and public key, or the
+    /// same bytes encoded in hex or base64 text.
     pub fn from_file(filename: &str) -> Result<Self> {
-        let keys_str = std::fs::read_to_string(filename)
+        let bytes = std::fs::read(filename)
             .map_err(|e| format_err!("failed to read the keypair file: {}", e))?;
+        let text = match std::str::from_utf8(&bytes) {
+            Ok(text) if !text.trim().is_empty() => text.trim(),
+            _ => return Self::from_bytes(&bytes),
+        };
+        if text.starts_with('{') {
+            let json: Value = serde_json::from_str(text)
+                .map_err(|e| format_err!("failed to load keypair: {}", e))?;
+            if json.get(ENCRYPTED_FIELD).is_some() {
+                return Self::from_encrypted_json(&json, &read_password()?);
+            }
+            return Self::from_json(json);
+        }
+        if let Ok(decoded) = hex::decode(text) {
+            return Self::from_bytes(&decoded);
+        }
+        match base64::decode(text) {
+            Ok(decoded) => Self::from_bytes(&decoded),
+            Err(_) => Self::from_bytes(&bytes),
+        }
+    }
+
+    fn from_json(json: Value) -> Result<Self> {
         #[derive(Deserialize)]
         struct KeyPair {
             pub public: String,
             pub secret: String,
         }
-        let keys: KeyPair = serde_json::from_str(&keys_str)
+        let keys: KeyPair = serde_json::from_value(json)
             .map_err(|e| format_err!("failed to load keypair: {}", e))?;
         let private = hex::decode(keys.secret)
             .map_err(|e| format_err!("failed to decode private key: {}", e))?;
@@ -43,4 +98,495 @@ impl Keypair {
             public: Ed25519PublicKey::from_bytes(&public_bytes)?,
         })
     }
+
+    /// Creates the keypair from the 32-byte secret or 64 bytes of the secret and the public key.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match bytes.len() {
+            32 => Self::from_secret(bytes),
+            64 => {
+                let pair = Self::from_secret(&bytes[..32])?;
+                if pair.public.to_bytes()[..] != bytes[32..] {
+                    bail!("public key does not match the secret key");
+                }
+                Ok(pair)
+            }
+            len => bail!("invalid key length {}, must be 32 or 64 bytes", len),
+        }
+    }
+
+    fn from_encrypted_json(json: &Value, password: &str) -> Result<Self> {
+        let field = |name: &str| {
+            json[name]
+                .as_str()
+                .ok_or_else(|| format_err!("encrypted key file has no {} field", name))
+        };
+        let salt = hex::decode(field("salt")?)?;
+        let nonce = hex::decode(field("nonce")?)?;
+        let encrypted = base64::decode(field(ENCRYPTED_FIELD)?)?;
+        let iterations = json["iterations"]
+            .as_u64()
+            .ok_or_else(|| format_err!("encrypted key file has no iterations field"))?;
+        if nonce.len() != NONCE_LEN {
+            bail!("invalid nonce length {}", nonce.len());
+        }
+        let cipher = Aes256Gcm::new_from_slice(&password_key(password, &salt, iterations as u32))
+            .map_err(|e| format_err!("failed to create cipher: {}", e))?;
+        let secret = cipher
+            .decrypt(Nonce::from_slice(&nonce), encrypted.as_slice())
+            .map_err(|_| format_err!("failed to decrypt the key: wrong password"))?;
+        let pair = Self::from_bytes(&secret)?;
+        if hex::decode(field("public")?)? != pair.public.to_bytes() {
+            bail!("public key in the encrypted key file does not match the decrypted secret key");
+        }
+        Ok(pair)
+    }
+
+    /// Creates the keypair from the 32-byte ed25519 secret.
+    pub fn from_secret(secret: &[u8]) -> Result<Self> {
+        let private = ed25519_create_private_key(secret)?;
+        let public = Ed25519PublicKey::from_bytes(&private.verifying_key())?;
+        Ok(Self { private, public })
+    }
+
+    pub fn generate() -> Result<Self> {
+        Self::from_secret(&rand::random::<[u8; 32]>())
+    }
+
+    /// Creates the same keypair for the same seed string, useful for reproducible tests.
+    pub fn from_seed(seed: &str) -> Result<Self> {
+        Self::from_secret(&Sha256::digest(seed.as_bytes()))
+    }
+
+    /// Derives the keypair from the BIP39 mnemonic (english words) by the BIP32 path
+    /// the same way as the ecosystem wallets and SDK do.
+    pub fn from_mnemonic(phrase: &str, path: &str) -> Result<Self> {
+        let mnemonic = Mnemonic::parse_in(Language::English, phrase)
+            .map_err(|e| format_err!("invalid mnemonic phrase: {}", e))?;
+        let secret = derive_hd_secret(&mnemonic.to_seed(""), path)?;
+        Self::from_secret(&secret)
+    }
+
+    /// Serializes the keypair in the format read by `from_file`.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "public": hex::encode(self.public.to_bytes()),
+            "secret": hex::encode(self.private.as_bytes()),
+        })
+        .to_string()
+    }
+
+    /// Serializes the secret key encrypted with AES-256-GCM by the key derived from the password.
+    pub fn to_encrypted_json(&self, password: &str) -> Result<String> {
+        let salt = rand::random::<[u8; 16]>();
+        let nonce = rand::random::<[u8; NONCE_LEN]>();
+        let cipher = Aes256Gcm::new_from_slice(&password_key(password, &salt, PBKDF2_ITERATIONS))
+            .map_err(|e| format_err!("failed to create cipher: {}", e))?;
+        let encrypted = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                self.private.as_bytes().as_slice(),
+            )
+            .map_err(|_| format_err!("failed to encrypt the key"))?;
+        Ok(serde_json::json!({
+            "public": hex::encode(self.public.to_bytes()),
+            "encrypted": base64::encode(encrypted),
+            "salt": hex::encode(salt),
+            "nonce": hex::encode(nonce),
+            "iterations": PBKDF2_ITERATIONS,
+        })
+        .to_string())
+    }
+
+    /// Saves the keypair readable only by the owner, the existing file is replaced
+    /// only if `overwrite` is set.
+    pub fn save(&self, filename: &str, overwrite: bool) -> Status {
+        write_key_file(filename, &self.to_json(), overwrite)
+    }
+
+    pub fn save_encrypted(&self, filename: &str, password: &str, overwrite: bool) -> Status {
+        write_key_file(filename, &self.to_encrypted_json(password)?, overwrite)
+    }
+}
+
+fn write_key_file(filename: &str, content: &str, overwrite: bool) -> Status {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    match overwrite {
+        true => options.create(true).truncate(true),
+        false => options.create_new(true),
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(filename)
+        .map_err(|e| format_err!("failed to save keypair to {}: {}", filename, e))?;
+    // the mode is applied only to the new file
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(content.as_bytes())
+        .map_err(|e| format_err!("failed to save keypair to {}: {}", filename, e))
+}
+
+/// Signs data with the key which is not necessarily loaded into memory.
+pub trait Signer {
+    fn public_key(&self) -> Result<[u8; 32]>;
+    fn sign(&self, data: &[u8]) -> Result<[u8; 64]>;
+}
+
+impl Signer for Keypair {
+    fn public_key(&self) -> Result<[u8; 32]> {
+        Ok(self.public.to_bytes())
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<[u8; 64]> {
+        ed25519_sign_with_secret(self.private.as_bytes(), data)
+    }
+}
+
+/// Signer running an external command (e.g. HSM client): `<command> public` prints
+/// the public key and `<command> sign <data>` prints the signature, all in hex.
+pub struct CommandSigner {
+    program: String,
+    args: Vec<String>,
+}
+
+impl CommandSigner {
+    pub fn new(command: &str) -> Result<Self> {
+        let mut words = command.split_whitespace().map(str::to_owned);
+        let program = words
+            .next()
+            .ok_or_else(|| format_err!("signer command is empty"))?;
+        Ok(Self {
+            program,
+            args: words.collect(),
+        })
+    }
+
+    fn run(&self, args: &[&str]) -> Result<Vec<u8>> {
+        let output = std::process::Command::new(&self.program)
+            .args(&self.args)
+            .args(args)
+            .output()
+            .map_err(|e| format_err!("failed to run signer {}: {}", self.program, e))?;
+        if !output.status.success() {
+            bail!(
+                "signer {} failed with {}: {}",
+                self.program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        hex::decode(stdout.trim())
+            .map_err(|e| format_err!("signer {} printed invalid hex: {}", self.program, e))
+    }
+}
+
+impl Signer for CommandSigner {
+    fn public_key(&self) -> Result<[u8; 32]> {
+        self.run(&["public"])?
+            .try_into()
+            .map_err(|v: Vec<u8>| format_err!("invalid public key length {}", v.len()))
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<[u8; 64]> {
+        self.run(&["sign", &hex::encode(data)])?
+            .try_into()
+            .map_err(|v: Vec<u8>| format_err!("invalid signature length {}", v.len()))
+    }
+}
+
+/// Loads the signer from the key file or, for `cmd:<command>`, creates the command signer.
+pub fn load_signer(key: &str) -> Result<Box<dyn Signer>> {
+    match key.strip_prefix(COMMAND_SIGNER_PREFIX) {
+        Some(command) => Ok(Box::new(CommandSigner::new(command)?)),
+        None => Ok(Box::new(Keypair::from_file(key)?)),
+    }
+}
+
+/// Disables reading of the password from stdin, the password can be set
+/// only by the environment variable.
+pub fn disable_password_prompt() {
+    PASSWORD_PROMPT.store(false, Ordering::Relaxed);
+}
+
+/// Reads the password of the encrypted key file from the environment variable or stdin.
+/// The password typed in the terminal is not echoed.
+pub fn read_password() -> Result<String> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    if !PASSWORD_PROMPT.load(Ordering::Relaxed) {
+        bail!(
+            "the key file is encrypted, set its password in {} environment variable",
+            PASSWORD_ENV
+        );
+    }
+    prompt_password("Key password: ")
+}
+
+/// Reads the password for a new encrypted key file, the password typed in stdin
+/// is asked twice to protect from typos.
+pub fn read_new_password() -> Result<String> {
+    if let Ok(password) = std::env::var(PASSWORD_ENV) {
+        return Ok(password);
+    }
+    let password = prompt_password("New key password: ")?;
+    if prompt_password("Repeat key password: ")? != password {
+        bail!("passwords do not match")
+    }
+    Ok(password)
+}
+
+fn prompt_password(prompt: &str) -> Result<String> {
+    if std::io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt)
+            .map_err(|e| format_err!("failed to read password: {}", e));
+    }
+    eprint!("{}", prompt);
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .map_err(|e| format_err!("failed to read password: {}", e))?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn password_key(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+pub fn generate_mnemonic(word_count: usize) -> Result<String> {
+    let entropy_len = match word_count {
+        12 | 15 | 18 | 21 | 24 => word_count / 3 * 4,
+        _ => bail!(
+            "invalid word count {}, must be 12, 15, 18, 21 or 24",
+            word_count
+        ),
+    };
+    let entropy: Vec<u8> = (0..entropy_len).map(|_| rand::random::<u8>()).collect();
+    let mnemonic = Mnemonic::from_entropy_in(Language::English, &entropy)
+        .map_err(|e| format_err!("failed to generate mnemonic: {}", e))?;
+    Ok(mnemonic.to_string())
+}
+
+fn parse_hd_path(path: &str) -> Result<Vec<u32>> {
+    let mut parts = path.split('/');
+    if parts.next() != Some("m") {
+        bail!("derivation path {} must start with m/", path);
+    }
+    parts
+        .map(|part| {
+            let (index, hardened) = match part.strip_suffix('\'') {
+                Some(index) => (index, HARDENED_INDEX),
+                None => (part, 0),
+            };
+            match index.parse::<u32>() {
+                Ok(index) if index < HARDENED_INDEX => Ok(index | hardened),
+                _ => bail!("invalid index {} in derivation path {}", part, path),
+            }
+        })
+        .collect()
+}
+
+fn hmac_sha512(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key)
+        .map_err(|e| format_err!("failed to create hmac: {}", e))?;
+    mac.update(data);
+    Ok(mac.finalize().into_bytes().to_vec())
+}
+
+/// BIP32 derivation of the secp256k1 private key, the result is used as the ed25519 secret.
+fn derive_hd_secret(seed: &[u8], path: &str) -> Result<[u8; 32]> {
+    let secp_err = |e: libsecp256k1::Error| format_err!("key derivation failed: {:?}", e);
+    let master = hmac_sha512(b"Bitcoin seed", seed)?;
+    let mut key = libsecp256k1::SecretKey::parse_slice(&master[..32]).map_err(secp_err)?;
+    let mut chain_code = master[32..].to_vec();
+    for index in parse_hd_path(path)? {
+        let mut data = Vec::with_capacity(37);
+        if index & HARDENED_INDEX != 0 {
+            data.push(0);
+            data.extend_from_slice(&key.serialize());
+        } else {
+            let public = libsecp256k1::PublicKey::from_secret_key(&key);
+            data.extend_from_slice(&public.serialize_compressed());
+        }
+        data.extend_from_slice(&index.to_be_bytes());
+        let child = hmac_sha512(&chain_code, &data)?;
+        let tweak = libsecp256k1::SecretKey::parse_slice(&child[..32]).map_err(secp_err)?;
+        key.tweak_add_assign(&tweak).map_err(secp_err)?;
+        chain_code = child[32..].to_vec();
+    }
+    Ok(key.serialize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon abandon \
+                            abandon abandon abandon about";
+
+    #[test]
+    fn test_parse_hd_path() {
+        assert_eq!(
+            parse_hd_path(DEFAULT_HD_PATH).unwrap(),
+            vec![
+                44 | HARDENED_INDEX,
+                396 | HARDENED_INDEX,
+                HARDENED_INDEX,
+                0,
+                0
+            ]
+        );
+        assert_eq!(parse_hd_path("m").unwrap(), Vec::<u32>::new());
+        assert!(parse_hd_path("44'/0").is_err());
+        assert!(parse_hd_path("m/x").is_err());
+        assert!(parse_hd_path("m/2147483648").is_err());
+    }
+
+    #[test]
+    fn test_derive_hd_secret() {
+        // BIP32 test vector 1
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        assert_eq!(
+            hex::encode(derive_hd_secret(&seed, "m/0'/1").unwrap()),
+            "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368"
+        );
+    }
+
+    #[test]
+    fn test_from_mnemonic() {
+        let pair = Keypair::from_mnemonic(MNEMONIC, DEFAULT_HD_PATH).unwrap();
+        assert_eq!(
+            hex::encode(pair.private.as_bytes()),
+            "f3d1b6f23d9d0df2dec233b7ded22662c55b4fb5fd2b3a3ffeb83d6834562456"
+        );
+        assert_eq!(
+            hex::encode(pair.public.to_bytes()),
+            "77c647c114a311fc70d8f6d52d6ffef1ee105e36eba5a8769e3ce4d1a25bde25"
+        );
+        assert!(Keypair::from_mnemonic("abandon abandon", DEFAULT_HD_PATH).is_err());
+    }
+
+    #[test]
+    fn test_generate_and_save() {
+        let phrase = generate_mnemonic(24).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+        assert!(Keypair::from_mnemonic(&phrase, DEFAULT_HD_PATH).is_ok());
+        assert!(generate_mnemonic(13).is_err());
+
+        let pair = Keypair::from_seed("test").unwrap();
+        let same = Keypair::from_seed("test").unwrap();
+        assert_eq!(pair.public.to_bytes(), same.public.to_bytes());
+        assert_ne!(
+            pair.public.to_bytes(),
+            Keypair::generate().unwrap().public.to_bytes()
+        );
+
+        let filename =
+            std::env::temp_dir().join(format!("tvm_linker_test_keys_{}.json", std::process::id()));
+        let filename = filename.to_str().unwrap();
+        pair.save(filename, false).unwrap();
+        let loaded = Keypair::from_file(filename).unwrap();
+        assert_eq!(loaded.private.as_bytes(), pair.private.as_bytes());
+        assert_eq!(loaded.public.to_bytes(), pair.public.to_bytes());
+
+        // the existing key is replaced only explicitly
+        let other = Keypair::from_seed("other").unwrap();
+        assert!(other.save(filename, false).is_err());
+        let loaded = Keypair::from_file(filename).unwrap();
+        assert_eq!(loaded.private.as_bytes(), pair.private.as_bytes());
+        other.save(filename, true).unwrap();
+        let loaded = Keypair::from_file(filename).unwrap();
+        assert_eq!(loaded.private.as_bytes(), other.private.as_bytes());
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(filename).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_key_formats() {
+        let pair = Keypair::from_seed("formats").unwrap();
+        let secret = pair.private.as_bytes().to_vec();
+        let mut full = secret.clone();
+        full.extend_from_slice(&pair.public.to_bytes());
+
+        let filename = std::env::temp_dir().join("tvm_linker_test_key_formats");
+        let filename = filename.to_str().unwrap();
+        for content in [
+            secret.clone(),
+            full.clone(),
+            hex::encode(&full).into_bytes(),
+            base64::encode(&secret).into_bytes(),
+            format!("{}\n", base64::encode(&full)).into_bytes(),
+            pair.to_json().into_bytes(),
+        ] {
+            std::fs::write(filename, content).unwrap();
+            let loaded = Keypair::from_file(filename).unwrap();
+            assert_eq!(loaded.public.to_bytes(), pair.public.to_bytes());
+        }
+        full[40] ^= 1;
+        std::fs::write(filename, &full).unwrap();
+        assert!(Keypair::from_file(filename).is_err());
+        std::fs::write(filename, &secret[..20]).unwrap();
+        assert!(Keypair::from_file(filename).is_err());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_key() {
+        let pair = Keypair::from_seed("encrypted").unwrap();
+        let json: Value = serde_json::from_str(&pair.to_encrypted_json("secret").unwrap()).unwrap();
+        assert!(!json
+            .to_string()
+            .contains(&hex::encode(pair.private.as_bytes())));
+        let loaded = Keypair::from_encrypted_json(&json, "secret").unwrap();
+        assert_eq!(loaded.private.as_bytes(), pair.private.as_bytes());
+        assert!(Keypair::from_encrypted_json(&json, "wrong").is_err());
+
+        let mut tampered = json.clone();
+        tampered["public"] =
+            hex::encode(Keypair::from_seed("other").unwrap().public.to_bytes()).into();
+        assert!(Keypair::from_encrypted_json(&tampered, "secret").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_signer() {
+        use std::os::unix::fs::PermissionsExt;
+        let pair = Keypair::from_seed("command").unwrap();
+        let signature = pair.sign(b"data").unwrap();
+        let script = std::env::temp_dir().join("tvm_linker_test_signer.sh");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\nif [ \"$1\" = public ]; then echo {}; else echo {}; fi\n",
+                hex::encode(pair.public.to_bytes()),
+                hex::encode(signature)
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let signer = load_signer(&format!("cmd:{}", script.to_str().unwrap())).unwrap();
+        assert_eq!(signer.public_key().unwrap(), pair.public.to_bytes());
+        assert_eq!(signer.sign(b"data").unwrap(), signature);
+        std::fs::remove_file(&script).unwrap();
+        assert!(load_signer("cmd:not_existing_signer")
+            .unwrap()
+            .public_key()
+            .is_err());
+        assert!(load_signer("cmd: ").is_err());
+    }
 }
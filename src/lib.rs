@@ -12,7 +12,15 @@
  */
 
 pub mod abi;
+pub mod coverage;
+pub mod dap;
+pub mod debugger;
 pub mod keyman;
 pub mod printer;
+pub mod profiler;
 pub mod program;
+pub mod sandbox;
+pub mod scenario;
 pub mod testcall;
+pub mod tracefile;
+pub mod transaction;
@@ -12,27 +12,42 @@
  */
 
 mod abi;
+mod coverage;
+mod dap;
+mod debugger;
 mod keyman;
 mod printer;
+mod profiler;
 mod program;
+mod sandbox;
+mod scenario;
 mod testcall;
+mod tracefile;
+mod transaction;
 
 use anyhow::{bail, format_err};
-use clap::{clap_app, ArgMatches};
+use clap::{clap_app, App, ArgMatches};
 use std::{env, fs::File, io::Write, str::FromStr};
 
-use ever_block::{AccountId, BocWriter, Result, SliceData, Status, UInt256};
+use ever_abi::contract::{AbiVersion, ABI_VERSION_1_0};
 use ever_block::{
-    ConfigParams, Deserializable, ExternalInboundMessageHeader, InternalMessageHeader, Message,
-    MsgAddressInt, MsgAddressIntOrNone, Serializable, StateInit,
+    Account, Deserializable, ExternalInboundMessageHeader, InternalMessageHeader, Message,
+    MsgAddressInt, MsgAddressIntOrNone, OutAction, OutActions, Serializable, StateInit,
 };
+use ever_block::{AccountId, BocWriter, Cell, Result, SliceData, Status, UInt256};
 
-use abi::{build_abi_body, decode_body, load_abi_contract, load_abi_json_string};
-use keyman::Keypair;
-use program::{get_now, load_from_file, save_to_file};
-use testcall::{call_contract, MsgInfo, TestCallParams, TraceLevel};
+use abi::{
+    build_abi_body, build_initial_data, decode_body, decode_data, load_abi_contract,
+    load_abi_json_string,
+};
+use keyman::{load_signer, Keypair};
+use program::{
+    compile_code_with_libs, decode_hex_string, get_now, load_from_file, save_debug_info,
+    save_to_file,
+};
+use testcall::{call_contract, get_capabilities, BlockInfo, MsgInfo, TestCallParams, TraceLevel};
 
-const DEFAULT_CAPABILITIES: u64 = 0x880116ae; // Default capabilities on the main network
+const PROFILE_TOP: usize = 20;
 
 fn main() -> std::result::Result<(), i32> {
     linker_main().map_err(|err_str| {
@@ -41,41 +56,87 @@ fn main() -> std::result::Result<(), i32> {
     })
 }
 
-fn linker_main() -> Status {
-    let build_info = format!(
-        "v{}\nBUILD_GIT_COMMIT: {}\nBUILD_GIT_DATE:   {}\nBUILD_TIME:       {}",
-        env!("CARGO_PKG_VERSION"),
-        env!("BUILD_GIT_COMMIT"),
-        env!("BUILD_GIT_DATE"),
-        env!("BUILD_TIME"),
-    );
-    let matches = clap_app!(tvm_linker =>
-        (version: build_info.as_str())
+fn linker_app(build_info: &str) -> App<'_, '_> {
+    clap_app!(tvm_linker =>
+        (version: build_info)
         (about: "Tool for executing TVM code")
+        (@subcommand compile =>
+            (@setting AllowNegativeNumbers)
+            (about: "Compile contract from TVM assembler source to tvc file")
+            (version: build_info)
+            (@arg INPUT: +required +takes_value "TVM assembler source file")
+            (@arg LIB: --lib +takes_value +multiple number_of_values(1) "Library source file linked together with the contract code (can be used several times)")
+            (@arg ABI_JSON: -a --("abi-json") +takes_value "Supplies contract ABI to build initial contract data")
+            (@arg OUT_FILE: -o --output +takes_value "Output tvc file name (if not specified the file is named after the contract address)")
+            (@arg DEBUG_MAP: --("debug-map") +takes_value "Output debug map file name (<tvc name>.debug.json by default)")
+            (@arg WORKCHAIN: -w --workchain +takes_value "Workchain id used to print contract address, 0 by default")
+            (@arg PRINT_CODE: --("print-code") "Prints code cell and does not generate tvc file")
+            (@arg SILENT: --silent "Prints only the output file name in json format")
+        )
+        (@subcommand setdata =>
+            (@setting AllowNegativeNumbers)
+            (about: "Set contract data fields and public key in tvc file")
+            (version: build_info)
+            (@arg INPUT: +required +takes_value "Contract tvc file")
+            (@arg ABI_JSON: -a --("abi-json") +required +takes_value "Supplies json file with contract ABI")
+            (@arg DATA: --data +takes_value "Json object with values of the data fields (can be passed via filename)")
+            (@arg PUBKEY: --pubkey +takes_value conflicts_with[SIGN] "Public key in hex format")
+            (@arg SIGN: --setkey +takes_value "Loads public key from the keypair file")
+            (@arg OUT_FILE: -o --output +takes_value "Output tvc file name (if not specified the file is named after the contract address)")
+            (@arg WORKCHAIN: -w --workchain +takes_value "Workchain id used to print contract address, 0 by default")
+        )
         (@subcommand decode =>
             (about: "Take apart a message boc or a tvc file")
-            (version: build_info.as_str())
+            (version: build_info)
             (@arg INPUT: +required +takes_value "BOC file")
             (@arg TVC: --tvc "BOC file is tvc file")
+            (@arg ABI_JSON: -a --("abi-json") +takes_value requires[TVC] "Decodes contract data with the fields described in ABI")
         )
         (@subcommand test =>
             (@setting AllowLeadingHyphen)
             (about: "Execute contract in test environment")
-            (version: build_info.as_str())
+            (version: build_info)
             (@arg SOURCE: -s --source +takes_value "Contract source file")
             (@arg BODY: --body +takes_value "Body for external inbound message (a bitstring like x09c_ or a hex string)")
             (@arg BODY_FROM_BOC: --("body-from-boc") +takes_value "Body from message boc file")
-            (@arg SIGN: --sign +takes_value "Signs body with private key from defined file")
+            (@arg SIGN: --sign +takes_value "Signs body with private key from defined file (json, raw, hex, base64 or encrypted) or with cmd:<command> external signer")
+            (@arg ABI_VERSION: --("abi-version") +takes_value requires[BODY] "ABI version defining the layout of the signed --body (1.0 by default)")
             (@arg TRACE: --trace "Prints last command name, stack and registers after each executed TVM command")
             (@arg TRACE_MIN: --("trace-minimal") "Prints minimal trace")
             (@arg DECODEC6: --("decode-c6") "Prints last command name, stack and registers after each executed TVM command")
             (@arg INTERNAL: --internal +takes_value "Emulates inbound internal message with value instead of external message")
             (@arg BOUNCED: --bounced requires[INTERNAL] "Emulates bounced message, can be used only with --internal option.")
             (@arg BALANCE: --balance +takes_value "Emulates supplied account balance")
+            (@arg ACCOUNT: --account conflicts_with[ADDRESS] "Treats INPUT as a full account boc file: address, balance and state are read from it and the updated account is saved back")
+            (@arg OUTPUT: -o --output +takes_value "Saves the new contract state to the file instead of the input file")
+            (@arg NO_SAVE: --("no-save") conflicts_with[OUTPUT] conflicts_with[SAVE_ON_FAILURE] "Does not save the new contract state")
+            (@arg SAVE_ON_FAILURE: --("save-on-failure") "Saves the contract state even if the execution fails (e.g. fees charged in --full-trx mode)")
             (@arg SRCADDR: --src +takes_value "Supplies message source address")
+            (@arg BOUNCE: --bounce +takes_value requires[INTERNAL] "Sets bounce flag of the internal message (true or false), by default it is the opposite of --bounced")
+            (@arg FWD_FEE: --("fwd-fee") +takes_value requires[INTERNAL] "Sets forwarding fee of the internal message")
+            (@arg CREATED_LT: --("created-lt") +takes_value requires[INTERNAL] "Sets logical time of the internal message creation")
+            (@arg INIT_STATE: --("init-state") +takes_value "Attaches StateInit from the TVC file to the inbound message")
+            (@arg MSG_BOC: --("msg-boc") +takes_value conflicts_with[BODY] conflicts_with[BODY_FROM_BOC] conflicts_with[INTERNAL] conflicts_with[TICKTOCK] conflicts_with[ABI_METHOD] conflicts_with[INIT_STATE] conflicts_with[SIGN] "Uses the inbound message from the boc file as is, all headers are preserved")
             (@arg NOW: --now +takes_value "Supplies transaction creation unixtime")
             (@arg TICKTOCK: --ticktock +takes_value conflicts_with[BODY] "Emulates ticktock transaction in masterchain, 0 for tick and -1 for tock")
             (@arg GASLIMIT: -l --("gas-limit") +takes_value "Defines gas limit for tvm execution")
+            (@arg BLOCK_LT: --("block-lt") +takes_value "Supplies logical time of the block")
+            (@arg TRANS_LT: --("trans-lt") +takes_value "Supplies logical time of the transaction")
+            (@arg RAND_SEED: --("rand-seed") +takes_value "Supplies random seed of the block in hex (zero by default), the account seed is derived from it")
+            (@arg INIT_CODE_HASH: --("init-code-hash") +takes_value "Supplies initial code hash in hex (hash of the current code by default)")
+            (@arg STORAGE_FEE: --("storage-fee") +takes_value "Supplies collected storage fee")
+            (@arg DUE_PAYMENT: --("due-payment") +takes_value "Supplies due payment of the account")
+            (@arg PREV_BLOCKS: --("prev-blocks") +takes_value "Supplies json file with last masterchain blocks and previous key block")
+            (@arg REAL_GAS: --("real-gas") requires[CONFIG] conflicts_with[FULL_TRX] "Calculates gas limit and credit from the message value and gas prices in config parameters 20/21 like the validator does")
+            (@arg FULL_TRX: --("full-trx") conflicts_with[GASLIMIT] "Emulates the whole transaction (storage, credit, compute, action and bounce phases) using config parameters")
+            (@arg JSON: --json conflicts_with[TRACE] conflicts_with[TRACE_MIN] conflicts_with[DECODEC6] conflicts_with[FULL_TRX] "Prints the result of the execution as a json document")
+            (@arg DEBUG: --debug conflicts_with[JSON] conflicts_with[FULL_TRX] conflicts_with[TRACE] conflicts_with[TRACE_MIN] "Runs interactive debugger with breakpoints on source lines and instructions")
+            (@arg PROFILE: --profile conflicts_with[JSON] conflicts_with[FULL_TRX] "Prints gas and instruction count aggregated by source line and function")
+            (@arg PROFILE_OUT: --("profile-out") +takes_value conflicts_with[JSON] conflicts_with[FULL_TRX] "Saves gas profile as collapsed stacks for flamegraph tools")
+            (@arg TRACE_OUT: --("trace-out") +takes_value conflicts_with[FULL_TRX] "Saves executed instructions with gas and source positions to the trace file")
+            (@arg TRACE_STACK: --("trace-stack") requires[TRACE_OUT] "Saves stack snapshot for each instruction to the trace file")
+            (@arg COVERAGE: --coverage +takes_value conflicts_with[FULL_TRX] "Merges executed source lines into the lcov coverage file")
+            (@arg DECODE_DATA: --("decode-data") requires[ABI_JSON] "Prints contract data decoded with the ABI before and after the execution")
             (@arg CONFIG: --config +takes_value "Imports config parameters from a config contract TVC")
             (@arg INPUT: +required +takes_value "TVM assembler source file or contract name if used with test subcommand")
             (@arg ADDRESS: --address +takes_value "Contract address, which can be obtained from the contract with `address(this)`. If not specified address can be obtained from the INPUT argument or set to zero.")
@@ -85,35 +146,203 @@ fn linker_main() -> Status {
             (@arg ABI_PARAMS: -p --("abi-params") +takes_value conflicts_with[BODY] "Supplies ABI arguments for the contract method (can be passed via filename). Can be not specified for empty parameters.")
             (@arg ABI_HEADER: -r --("abi-header") +takes_value conflicts_with[BODY] conflicts_with[INTERNAL] "Supplies ABI header")
         )
+        (@subcommand ("run-get") =>
+            (@setting AllowNegativeNumbers)
+            (about: "Run get-method of the contract")
+            (version: build_info)
+            (@arg INPUT: +required +takes_value "Contract tvc file")
+            (@arg METHOD: +required +takes_value "Get-method name or id (ABI function name if used with --abi-json)")
+            (@arg ARGS: +takes_value +multiple "Get-method arguments pushed to the stack: integers, null or base64 encoded BOCs")
+            (@arg ADDRESS: --address +takes_value "Contract address, zero address by default")
+            (@arg BALANCE: --balance +takes_value "Emulates supplied account balance")
+            (@arg NOW: --now +takes_value "Supplies transaction creation unixtime")
+            (@arg CONFIG: --config +takes_value "Imports config parameters from a config contract TVC")
+            (@arg BLOCK_LT: --("block-lt") +takes_value "Supplies logical time of the block")
+            (@arg TRANS_LT: --("trans-lt") +takes_value "Supplies logical time of the transaction")
+            (@arg RAND_SEED: --("rand-seed") +takes_value "Supplies random seed of the block in hex (zero by default), the account seed is derived from it")
+            (@arg INIT_CODE_HASH: --("init-code-hash") +takes_value "Supplies initial code hash in hex (hash of the current code by default)")
+            (@arg STORAGE_FEE: --("storage-fee") +takes_value "Supplies collected storage fee")
+            (@arg DUE_PAYMENT: --("due-payment") +takes_value "Supplies due payment of the account")
+            (@arg PREV_BLOCKS: --("prev-blocks") +takes_value "Supplies json file with last masterchain blocks and previous key block")
+            (@arg DEBUG_MAP: -d --("debug-map") +takes_value "Supplies debug info json file")
+            (@arg ABI_JSON: -a --("abi-json") +takes_value conflicts_with[ARGS] "Supplies json file with contract ABI to call the function and decode its output")
+            (@arg ABI_PARAMS: -p --("abi-params") +takes_value requires[ABI_JSON] "Supplies ABI arguments for the function (can be passed via filename)")
+            (@arg ABI_HEADER: -r --("abi-header") +takes_value requires[ABI_JSON] "Supplies ABI header")
+            (@arg TRACE: --trace "Prints last command name, stack and registers after each executed TVM command")
+            (@arg TRACE_MIN: --("trace-minimal") "Prints minimal trace")
+        )
+        (@subcommand replay =>
+            (about: "Reproduce compute phase of a transaction from exported account, message and config")
+            (version: build_info)
+            (@arg ACCOUNT: +required +takes_value "Account state boc file")
+            (@arg MESSAGE: +required +takes_value "Inbound message boc file")
+            (@arg CONFIG: --config +required +takes_value "Config parameters boc file or config contract TVC")
+            (@arg NOW: --now +takes_value "Supplies block unixtime (creation time of the internal message by default)")
+            (@arg BLOCK_LT: --("block-lt") +takes_value "Supplies logical time of the block")
+            (@arg TRANS_LT: --("trans-lt") +takes_value "Supplies logical time of the transaction")
+            (@arg RAND_SEED: --("rand-seed") +takes_value "Supplies random seed of the block in hex, the account seed is derived from it")
+            (@arg STORAGE_FEE: --("storage-fee") +takes_value "Supplies storage fee collected in the storage phase")
+            (@arg DUE_PAYMENT: --("due-payment") +takes_value "Supplies due payment of the account")
+            (@arg PREV_BLOCKS: --("prev-blocks") +takes_value "Supplies json file with last masterchain blocks and previous key block")
+            (@arg DEBUG_MAP: -d --("debug-map") +takes_value "Supplies debug info json file")
+            (@arg TRACE: --trace "Prints last command name, stack and registers after each executed TVM command")
+            (@arg TRACE_MIN: --("trace-minimal") "Prints minimal trace")
+            (@arg TRACE_OUT: --("trace-out") +takes_value "Saves executed instructions with gas and source positions to the trace file")
+            (@arg TRACE_STACK: --("trace-stack") requires[TRACE_OUT] "Saves stack snapshot for each instruction to the trace file")
+            (@arg DEBUG: --debug conflicts_with[TRACE] conflicts_with[TRACE_MIN] "Runs interactive debugger with breakpoints on source lines and instructions")
+        )
+        (@subcommand scenario =>
+            (about: "Execute a sequence of contract calls described in a JSON or YAML script")
+            (version: build_info)
+            (@arg INPUT: +required +takes_value "Scenario file (.json, .yaml or .yml)")
+            (@arg OUTPUT: -o --output +takes_value "Saves the final contract state to the tvc file")
+            (@arg COVERAGE: --coverage +takes_value "Merges executed source lines of all steps into the lcov coverage file")
+            (@arg TRACE: --trace "Prints last command name, stack and registers after each executed TVM command")
+            (@arg TRACE_MIN: --("trace-minimal") "Prints minimal trace")
+        )
+        (@subcommand sandbox =>
+            (about: "Emulate several accounts delivering outbound internal messages between them")
+            (version: build_info)
+            (@arg INPUT: +required +takes_value "Sandbox json file with accounts and initial messages")
+            (@arg MAX_MESSAGES: --("max-messages") +takes_value "Maximum number of delivered messages, 1000 by default")
+            (@arg SAVE: --save "Saves the final account states to their tvc files")
+            (@arg TRACE: --trace "Prints last command name, stack and registers after each executed TVM command")
+            (@arg TRACE_MIN: --("trace-minimal") "Prints minimal trace")
+        )
+        (@subcommand ("trace-diff") =>
+            (about: "Compare two trace files and report the first divergence")
+            (version: build_info)
+            (@arg FIRST: +required +takes_value "First trace file")
+            (@arg SECOND: +required +takes_value "Second trace file")
+            (@arg CONTEXT: -c --context +takes_value "Number of records printed around the divergence, 5 by default")
+        )
+        (@subcommand genkey =>
+            (about: "Generate keypair and save it to the json file in the format used by --sign and --setkey")
+            (version: build_info)
+            (@arg OUTPUT: +required +takes_value "Keypair json file")
+            (@arg SEED: --seed +takes_value conflicts_with[PHRASE] conflicts_with[GEN_PHRASE] "Derives the same keypair for the same seed string (for reproducible tests)")
+            (@arg PHRASE: --phrase +takes_value "Derives the keypair from the BIP39 mnemonic phrase")
+            (@arg GEN_PHRASE: --("gen-phrase") conflicts_with[PHRASE] "Generates a new mnemonic phrase and derives the keypair from it")
+            (@arg WORDS: --words +takes_value requires[GEN_PHRASE] "Number of words in the generated phrase, 12 by default")
+            (@arg PATH: --path +takes_value "BIP32 derivation path for the mnemonic phrase (with --phrase or --gen-phrase), m/44'/396'/0'/0/0 by default")
+            (@arg ENCRYPT: --encrypt "Encrypts the secret key with the password from TVM_LINKER_KEY_PASSWORD environment variable or stdin")
+            (@arg FORCE: -f --force "Overwrites the existing key file")
+        )
+        (@subcommand address =>
+            (@setting AllowNegativeNumbers)
+            (about: "Convert address between raw and user-friendly forms or derive the address of a contract")
+            (version: build_info)
+            (@arg INPUT: +required +takes_value "Raw (wc:hex or hex) or user-friendly (base64 or base64url) address, or a TVC file with --tvc")
+            (@arg TVC: --tvc "Derives the address from the StateInit of the TVC file")
+            (@arg WORKCHAIN: -w --workchain +takes_value "Workchain id for the TVC or the hex address without workchain, 0 by default")
+            (@arg NON_BOUNCEABLE: --("non-bounceable") "Prints the user-friendly address without the bounce flag")
+            (@arg TESTNET: --testnet "Prints the user-friendly address with the testnet flag")
+        )
+        (@subcommand dap =>
+            (about: "Run Debug Adapter Protocol server on stdin/stdout to debug contracts in editors")
+            (version: build_info)
+        )
         (@subcommand message =>
             (@setting AllowNegativeNumbers)
             (about: "Generate inbound message for the blockchain")
-            (version: build_info.as_str())
+            (version: build_info)
             (@arg INIT: -i --init "Generates constructor message with code and data of the contract")
             (@arg DATA: -d --data +takes_value "Supplies body for the message in hex format (empty data by default)")
-            (@arg INTERNAL: --internal +takes_value "Generates inbound internal message with provided value (instead of external message by default)")
-            (@arg WORKCHAIN: -w --workchain +takes_value "Supplies workchain id for the contract address")
+            (@arg INTERNAL: --internal +takes_value "Generates inbound internal message with provided value (instead of external message by default), extra currencies are passed as {\"main\": 100, \"extra\": {\"1\": 5}}")
+            (@arg SRC: --src +takes_value requires[INTERNAL] "Source address of the internal message, -1:5555...5555 by default")
+            (@arg BOUNCE: --bounce +takes_value requires[INTERNAL] "Sets bounce flag of the internal message (true or false), true by default")
+            (@arg CREATED_LT: --("created-lt") +takes_value requires[INTERNAL] "Logical time of the internal message creation")
+            (@arg CREATED_AT: --("created-at") +takes_value requires[INTERNAL] "Unix time of the internal message creation")
+            (@arg WORKCHAIN: -w --workchain +takes_value "Supplies workchain id for the contract address, 0 by default")
+            (@arg OUTPUT: -o --output +takes_value "Output file for the message boc, <first 8 chars of INPUT>-msg[-init][-body].boc by default")
+            (@arg STDOUT: --stdout +takes_value possible_value[base64 hex json] conflicts_with[OUTPUT] "Prints the message to stdout in the given format instead of saving it to the file")
             (@arg ABI_JSON: -a --("abi-json") +takes_value conflicts_with[DATA] "Supplies json file with contract ABI")
             (@arg ABI_METHOD: -m --("abi-method") +takes_value conflicts_with[DATA] "Supplies the name of the calling contract method")
             (@arg ABI_PARAMS: -p --("abi-params") +takes_value conflicts_with[DATA] "Supplies ABI arguments for the contract method")
             (@arg ABI_HEADER: -r --("abi-header") +takes_value conflicts_with[DATA] "Supplies ABI header")
-            (@arg SIGN: --setkey +takes_value "Loads existing keypair from the file")
+            (@arg SIGN: --setkey +takes_value "Loads existing keypair from the file (json, raw, hex, base64 or encrypted) or uses cmd:<command> external signer")
+            (@arg ABI_VERSION: --("abi-version") +takes_value requires[DATA] "ABI version defining the layout of the signed --data body (1.0 by default)")
             (@arg ADDRESS: --addr +takes_value "Optional destination address to support ABI 2.3")
             (@arg INPUT: +required +takes_value "TVM assembler source file or contract name")
         )
         (@setting SubcommandRequired)
-    ).get_matches();
+    )
+}
+
+fn linker_main() -> Status {
+    let build_info = format!(
+        "v{}\nBUILD_GIT_COMMIT: {}\nBUILD_GIT_DATE:   {}\nBUILD_TIME:       {}",
+        env!("CARGO_PKG_VERSION"),
+        env!("BUILD_GIT_COMMIT"),
+        env!("BUILD_GIT_DATE"),
+        env!("BUILD_TIME"),
+    );
+    let matches = linker_app(&build_info).get_matches();
+
+    //SUBCOMMAND COMPILE
+    if let Some(compile_matches) = matches.subcommand_matches("compile") {
+        return run_compile_subcmd(compile_matches);
+    }
+
+    //SUBCOMMAND SETDATA
+    if let Some(setdata_matches) = matches.subcommand_matches("setdata") {
+        return run_setdata_subcmd(setdata_matches);
+    }
 
     //SUBCOMMAND TEST
     if let Some(test_matches) = matches.subcommand_matches("test") {
         return run_test_subcmd(test_matches);
     }
 
+    //SUBCOMMAND RUN-GET
+    if let Some(run_get_matches) = matches.subcommand_matches("run-get") {
+        return run_get_subcmd(run_get_matches);
+    }
+
+    //SUBCOMMAND REPLAY
+    if let Some(replay_matches) = matches.subcommand_matches("replay") {
+        return run_replay_subcmd(replay_matches);
+    }
+
+    //SUBCOMMAND SCENARIO
+    if let Some(scenario_matches) = matches.subcommand_matches("scenario") {
+        return run_scenario_subcmd(scenario_matches);
+    }
+
+    //SUBCOMMAND SANDBOX
+    if let Some(sandbox_matches) = matches.subcommand_matches("sandbox") {
+        return run_sandbox_subcmd(sandbox_matches);
+    }
+
+    //SUBCOMMAND TRACE-DIFF
+    if let Some(trace_diff_matches) = matches.subcommand_matches("trace-diff") {
+        return run_trace_diff_subcmd(trace_diff_matches);
+    }
+
+    //SUBCOMMAND ADDRESS
+    if let Some(address_matches) = matches.subcommand_matches("address") {
+        return run_address_subcmd(address_matches);
+    }
+
+    //SUBCOMMAND GENKEY
+    if let Some(genkey_matches) = matches.subcommand_matches("genkey") {
+        return run_genkey_subcmd(genkey_matches);
+    }
+
+    //SUBCOMMAND DAP
+    if matches.subcommand_matches("dap").is_some() {
+        // stdin is the transport of the protocol, it can't be used to read passwords
+        keyman::disable_password_prompt();
+        dap::run(std::io::BufReader::new(std::io::stdin()), std::io::stdout())?;
+        return Ok(());
+    }
+
     //SUBCOMMAND DECODE
     if let Some(decode_matches) = matches.subcommand_matches("decode") {
         return decode_boc(
             decode_matches.value_of("INPUT").unwrap(),
             decode_matches.is_present("TVC"),
+            decode_matches.value_of("ABI_JSON"),
         );
     }
 
@@ -129,33 +358,44 @@ fn linker_main() -> Status {
         }
         suffix += ".boc";
 
+        let input = msg_matches.value_of("INPUT").unwrap();
+        let (name, dest_address) = message_destination(input, msg_matches.value_of("WORKCHAIN"))?;
         let msg_body = match msg_matches.value_of("DATA") {
             Some(data) => {
                 let buf = hex::decode(data)
                     .map_err(|e| format_err!("data argument has invalid format: {}", e))?;
                 let len = buf.len() * 8;
-                let body = SliceData::from_raw(buf, len);
+                let mut body = SliceData::from_raw(buf, len);
+                if let Some(key_file) = msg_matches.value_of("SIGN") {
+                    let abi_version = parse_abi_version(msg_matches.value_of("ABI_VERSION"))?;
+                    testcall::sign_body(&mut body, Some(key_file), abi_version, &dest_address)?;
+                }
                 Some(body)
             }
             None => build_body(
                 msg_matches,
-                msg_matches.value_of("ADDRESS").map(|s| s.to_string()),
+                msg_matches
+                    .value_of("ADDRESS")
+                    .map(program::parse_address)
+                    .transpose()?
+                    .map(|addr| addr.to_string()),
             )?,
         };
 
-        return build_message(
-            msg_matches.value_of("INPUT").unwrap(),
-            msg_matches.value_of("WORKCHAIN"),
-            msg_body,
-            msg_matches.is_present("INIT"),
-            &suffix,
-            msg_matches.is_present("INTERNAL"),
-        );
+        return build_message(msg_matches, &name, dest_address, msg_body, &suffix);
     }
 
     unreachable!()
 }
 
+fn parse_abi_version(version: Option<&str>) -> Result<AbiVersion> {
+    match version {
+        Some(version) => AbiVersion::parse(version)
+            .map_err(|e| format_err!("invalid ABI version {}: {}", version, e)),
+        None => Ok(ABI_VERSION_1_0),
+    }
+}
+
 fn parse_now(now: Option<&str>) -> Result<u32> {
     let now = match now {
         Some(now_str) => now_str
@@ -182,26 +422,19 @@ fn parse_ticktock(ticktock: Option<&str>) -> Result<Option<i8>> {
     }
 }
 
-fn decode_hex_string(hex_str: String) -> Result<(Vec<u8>, usize)> {
-    if hex_str.to_ascii_lowercase().starts_with('x') {
-        let buf = SliceData::from_string(&hex_str[1..])
-            .map_err(|_| format_err!("body {} is invalid literal slice", hex_str))?;
-        Ok((buf.get_bytestring(0), buf.remaining_bits()))
-    } else {
-        let buf = hex::decode(&hex_str)
-            .map_err(|_| format_err!("body {} is invalid hex string", hex_str))?;
-        let buf_bits = buf.len() * 8;
-        Ok((buf, buf_bits))
-    }
-}
-
-fn decode_boc(filename: &str, is_tvc: bool) -> Status {
+fn decode_boc(filename: &str, is_tvc: bool, abi_file: Option<&str>) -> Status {
     let (mut root_slice, orig_bytes) = program::load_stateinit(filename)?;
 
     println!("Encoded: {}\n", hex::encode(orig_bytes));
     if is_tvc {
         let state = StateInit::construct_from(&mut root_slice)?;
         println!("Decoded:\n{}", printer::state_init_printer(&state));
+        if let Some(abi_file) = abi_file {
+            println!(
+                "Decoded data:\n{}",
+                decode_data(abi_file, state.data.as_ref())?
+            );
+        }
     } else {
         let msg = Message::construct_from(&mut root_slice)?;
         println!("Decoded:\n{}", printer::msg_printer(&msg)?);
@@ -209,22 +442,103 @@ fn decode_boc(filename: &str, is_tvc: bool) -> Status {
     Ok(())
 }
 
+fn run_compile_subcmd(matches: &ArgMatches) -> Status {
+    let input = matches.value_of("INPUT").unwrap();
+    let libs = matches
+        .values_of("LIB")
+        .map(|values| values.collect::<Vec<_>>())
+        .unwrap_or_default();
+    let (code, debug_info) = compile_code_with_libs(input, &libs)?;
+
+    if matches.is_present("PRINT_CODE") {
+        println!("{}", code);
+        return Ok(());
+    }
+
+    let wc = match matches.value_of("WORKCHAIN") {
+        Some(w) => w.parse::<i8>()?,
+        None => 0,
+    };
+    let data = match matches.value_of("ABI_JSON") {
+        Some(abi_file) => build_initial_data(abi_file)?,
+        None => Cell::default(),
+    };
+    let state_init = StateInit {
+        code: Some(code),
+        data: Some(data),
+        ..Default::default()
+    };
+    let silent = matches.is_present("SILENT");
+    let tvc_name = save_to_file(state_init, matches.value_of("OUT_FILE"), wc, silent)?;
+
+    let debug_map_name = match matches.value_of("DEBUG_MAP") {
+        Some(name) => name.to_string(),
+        None => format!("{}.debug.json", tvc_name.trim_end_matches(".tvc")),
+    };
+    save_debug_info(&debug_info, &debug_map_name)?;
+    if !silent {
+        println!("Saved debug map to file {}", debug_map_name);
+    }
+    Ok(())
+}
+
+fn run_setdata_subcmd(matches: &ArgMatches) -> Status {
+    let input = matches.value_of("INPUT").unwrap();
+    let abi_file = matches.value_of("ABI_JSON").unwrap();
+    let values = match matches.value_of("DATA") {
+        Some(data) if data.find('{').is_none() => Some(
+            std::fs::read_to_string(data)
+                .map_err(|e| format_err!("failed to load data from file: {}", e))?,
+        ),
+        data => data.map(|data| data.to_owned()),
+    };
+    let pubkey = match (matches.value_of("PUBKEY"), matches.value_of("SIGN")) {
+        (Some(pubkey), _) => Some(
+            hex::decode(pubkey.trim_start_matches("0x"))
+                .map_err(|e| format_err!("failed to decode public key: {}", e))?
+                .try_into()
+                .map_err(|v: Vec<u8>| format_err!("invalid public key length {}", v.len()))?,
+        ),
+        (None, Some(key_file)) => Some(load_signer(key_file)?.public_key()?),
+        (None, None) => None,
+    };
+    let wc = match matches.value_of("WORKCHAIN") {
+        Some(w) => w.parse::<i8>()?,
+        None => 0,
+    };
+
+    let mut state_init = load_from_file(input)?;
+    state_init.data = Some(abi::update_data(
+        abi_file,
+        state_init.data,
+        values.as_deref(),
+        pubkey,
+    )?);
+    save_to_file(state_init, matches.value_of("OUT_FILE"), wc, false)?;
+    Ok(())
+}
+
 fn run_test_subcmd(matches: &ArgMatches) -> Status {
     let input = matches.value_of("INPUT").unwrap();
-    let addr_from_input = if hex::decode(input).is_ok() {
-        input.to_owned()
-    } else {
-        "0".repeat(64)
+    let account = match matches.is_present("ACCOUNT") {
+        true => Some(program::load_account(input)?),
+        false => None,
+    };
+    let addr_from_input = match account.as_ref().and_then(Account::get_addr) {
+        Some(addr) => addr.to_string(),
+        None if hex::decode(input).is_ok() => input.to_owned(),
+        None => "0".repeat(64),
     };
-    let address = matches.value_of("ADDRESS").unwrap_or(&addr_from_input);
+    let addr = program::parse_address(matches.value_of("ADDRESS").unwrap_or(&addr_from_input))?;
     let (body, sign) = match matches.value_of("BODY") {
         Some(hex_str) => {
             let (buf, buf_bits) = decode_hex_string(hex_str.to_string())?;
             let body = SliceData::from_raw(buf, buf_bits);
             (Some(body), Some(matches.value_of("SIGN")))
         }
-        None => (build_body(matches, Some(address.to_string()))?, None),
+        None => (build_body(matches, Some(addr.to_string()))?, None),
     };
+    let abi_version = parse_abi_version(matches.value_of("ABI_VERSION"))?;
 
     let ticktock = parse_ticktock(matches.value_of("TICKTOCK"))?;
     let now = parse_now(matches.value_of("NOW"))?;
@@ -261,11 +575,14 @@ fn run_test_subcmd(matches: &ArgMatches) -> Status {
         }
         res
     });
-    if let Some(map) = debug_map_filename.clone() {
-        println!("DEBUG_MAP: {map}");
+    let json = matches.is_present("JSON");
+    if !json {
+        if let Some(map) = debug_map_filename.clone() {
+            println!("DEBUG_MAP: {map}");
+        }
+        println!("TEST STARTED");
+        println!("body = {:?}", body);
     }
-    println!("TEST STARTED");
-    println!("body = {:?}", body);
 
     let mut msg_info = MsgInfo {
         balance: matches.value_of("INTERNAL"),
@@ -273,6 +590,25 @@ fn run_test_subcmd(matches: &ArgMatches) -> Status {
         now,
         bounced: matches.is_present("BOUNCED"),
         body,
+        bounce: matches
+            .value_of("BOUNCE")
+            .map(|v| v.parse::<bool>())
+            .transpose()
+            .map_err(|e| format_err!("invalid bounce flag: {}", e))?,
+        fwd_fee: matches
+            .value_of("FWD_FEE")
+            .map(|v| v.parse::<u64>())
+            .transpose()?
+            .unwrap_or_default(),
+        created_lt: matches
+            .value_of("CREATED_LT")
+            .map(|v| v.parse::<u64>())
+            .transpose()?,
+        state_init: matches
+            .value_of("INIT_STATE")
+            .map(load_from_file)
+            .transpose()?,
+        message: None,
     };
 
     if let Some(filename) = matches.value_of("BODY_FROM_BOC") {
@@ -281,63 +617,606 @@ fn run_test_subcmd(matches: &ArgMatches) -> Status {
         msg_info.body = msg.body();
     }
 
+    if let Some(filename) = matches.value_of("MSG_BOC") {
+        let (mut root_slice, _) = program::load_stateinit(filename)?;
+        let msg = Message::construct_from(&mut root_slice)?;
+        if msg.is_outbound_external() {
+            bail!("outbound external message cannot be used as an inbound message");
+        }
+        match msg.dst() {
+            Some(dst) if dst == addr => {}
+            dst => bail!(
+                "message destination {} differs from the contract address {}",
+                dst.map_or("none".to_string(), |dst| dst.to_string()),
+                addr
+            ),
+        }
+        msg_info.message = Some(msg);
+    }
+
     let gas_limit = matches
         .value_of("GASLIMIT")
         .map(|v| v.parse::<i64>())
         .transpose()?;
 
-    let mut trace_level = TraceLevel::None;
-    if matches.is_present("TRACE") {
-        trace_level = TraceLevel::Full;
-    } else if matches.is_present("TRACE_MIN") {
-        trace_level = TraceLevel::Minimal;
-    }
+    let trace_level = parse_trace_level(matches);
 
-    let input = if input.ends_with(".tvc") {
+    let input = if input.ends_with(".tvc") || account.is_some() {
         input.to_owned()
     } else {
         format!("{}.tvc", input)
     };
-    let addr = MsgAddressInt::from_str(address)?;
-    let state_init = load_from_file(&input)?;
+    let full_trx = matches.is_present("FULL_TRX");
+    let state_init = match &account {
+        Some(account) => match account.state_init() {
+            Some(state_init) => state_init.clone(),
+            // the whole transaction can deploy the uninit account or bounce the message
+            None if full_trx => StateInit::default(),
+            None => bail!(
+                "account {} is not active, it can be processed only with --full-trx",
+                input
+            ),
+        },
+        None => load_from_file(&input)?,
+    };
+    let account_balance = account
+        .as_ref()
+        .and_then(Account::balance)
+        .map(testcall::currency_to_arg);
     let config_cell_opt = matches.value_of("CONFIG").and_then(testcall::load_config);
 
-    let capabilities = match config_cell_opt {
-        Some(ref config_cell) => {
-            let config_params = ConfigParams::with_address_and_root(
-                UInt256::from_str(&"5".repeat(64)).unwrap(), // -1:5555...
-                config_cell.clone(),
-            );
-            config_params.capabilities()
+    let capabilities = get_capabilities(&config_cell_opt);
+    let debug_map_filename = debug_map_filename.unwrap_or_default();
+    let coverage = match matches.value_of("COVERAGE") {
+        Some(filename) => {
+            let debug_info = testcall::load_debug_info(&debug_map_filename);
+            let code = state_init.code.as_ref();
+            Some(coverage::Coverage::load(filename, code, debug_info.as_ref())?)
         }
-        None => DEFAULT_CAPABILITIES,
+        None => None,
     };
-    let (_, state_init, is_success) = call_contract(
-        addr,
-        state_init,
-        TestCallParams {
-            balance: matches.value_of("BALANCE"),
-            msg_info,
-            config: config_cell_opt,
-            key_file: sign,
-            ticktock,
-            gas_limit,
-            action_decoder: if matches.is_present("DECODEC6") {
-                Some(action_decoder)
-            } else {
-                None
-            },
-            trace_level,
-            debug_info: testcall::load_debug_info(&debug_map_filename.unwrap_or("".to_string())),
-            capabilities,
+    let trace_hook = match matches.is_present("DEBUG") {
+        true => {
+            let debug_info = testcall::load_debug_info(&debug_map_filename);
+            println!("Debugger started, type h for help");
+            Some(debugger::Debugger::new(debug_info).into_hook())
+        }
+        false => None,
+    };
+    let mut params = TestCallParams::new(msg_info)
+        .with_block_info(parse_block_info(matches)?)
+        .with_trace_level(trace_level)
+        .with_debug_info(testcall::load_debug_info(&debug_map_filename))
+        .with_abi_version(abi_version);
+    if let Some(key_file) = sign {
+        params = params.with_key_file(key_file);
+    }
+    if let Some(balance) = matches.value_of("BALANCE").or(account_balance.as_deref()) {
+        params = params.with_balance(balance);
+    }
+    if let Some(config) = config_cell_opt {
+        params = params.with_config(config);
+    }
+    if let Some(ticktock) = ticktock {
+        params = params.with_ticktock(ticktock);
+    }
+    if let Some(gas_limit) = gas_limit {
+        params = params.with_gas_limit(gas_limit);
+    }
+    if matches.is_present("REAL_GAS") {
+        params = params.with_real_gas();
+    }
+    if matches.is_present("TRACE_STACK") {
+        params = params.with_trace_stack();
+    } else if matches.is_present("PROFILE")
+        || matches.is_present("PROFILE_OUT")
+        || matches.is_present("TRACE_OUT")
+        || matches.is_present("COVERAGE")
+    {
+        params = params.with_trace_collection();
+    }
+    if let Some(trace_hook) = trace_hook {
+        params = params.with_trace_hook(trace_hook);
+    }
+    testcall::init_logger(trace_level == TraceLevel::Full)?;
+    let decode_c6 = matches.is_present("DECODEC6");
+    let data_abi = match matches.is_present("DECODE_DATA") {
+        true => matches.value_of("ABI_JSON"),
+        false => None,
+    };
+    let state_before = state_init.clone();
+    let output = matches.value_of("OUTPUT").unwrap_or(&input).to_owned();
+    let should_save = |success: bool| {
+        !matches.is_present("NO_SAVE") && (success || matches.is_present("SAVE_ON_FAILURE"))
+    };
+    if let (Some(abi_file), false, true) = (data_abi, json, state_before.data.is_some()) {
+        println!(
+            "Data before:\n{}",
+            decode_data(abi_file, state_before.data.as_ref())?
+        );
+    }
+
+    if full_trx {
+        let result = match account.clone() {
+            Some(account) => transaction::execute_account_transaction(account, params)?,
+            None => transaction::execute_transaction(addr, state_init, params)?,
+        };
+        println!("{}", printer::transaction_printer(&result.transaction)?);
+        if decode_c6 {
+            println!("Output messages:\n----------------");
+            result.transaction.iterate_out_msgs(|out_msg| {
+                println!("{}", printer::msg_printer(&out_msg)?);
+                if let Some(body) = out_msg.body() {
+                    action_decoder(body, out_msg.is_internal());
+                }
+                Ok(true)
+            })?;
+        }
+        println!(
+            "Account balance: {}",
+            printer::account_balance_printer(&result.account)
+        );
+        let success = result.is_success()?;
+        if let Some(state_init) = result.state_init() {
+            if let (Some(abi_file), true) = (data_abi, success) {
+                println!(
+                    "Data after:\n{}",
+                    decode_data(abi_file, state_init.data.as_ref())?
+                );
+            }
+            print!(
+                "{}",
+                printer::state_change_printer(&state_before, state_init)
+            );
+        }
+        if should_save(success) {
+            // the account is saved also if it stays uninit, its balance could change
+            match (&account, result.state_init()) {
+                (Some(_), _) => program::save_account(&result.account, &output)?,
+                (None, Some(state_init)) => save_test_state(&output, None, state_init.clone())?,
+                (None, None) => bail!("account state is lost after the transaction"),
+            }
+            print_saved_state(&input, &output);
+        }
+        println!("TEST COMPLETED");
+        return Ok(());
+    }
+
+    if json {
+        let result = call_contract(addr, state_init, params)?;
+        let decoder = |body, is_internal| {
+            let abi_file = matches.value_of("ABI_JSON")?;
+            let method = matches.value_of("ABI_METHOD")?;
+            let decoded = decode_body(abi_file, method, body, is_internal).ok()?;
+            serde_json::from_str(&decoded).ok()
+        };
+        let mut value = printer::execution_result_json(&result, decoder)?;
+        if let Some(abi_file) = data_abi {
+            let data_after = result.is_vm_success.then_some(result.c4.as_ref()).flatten();
+            value["data_before"] =
+                serde_json::from_str(&decode_data(abi_file, state_before.data.as_ref())?)?;
+            value["data_after"] = match data_after {
+                Some(data) => serde_json::from_str(&decode_data(abi_file, Some(data))?)?,
+                None => serde_json::Value::Null,
+            };
+        }
+        println!("{:#}", value);
+        if let Some(filename) = matches.value_of("TRACE_OUT") {
+            tracefile::save_trace(filename, &result.trace, matches.is_present("TRACE_STACK"))?;
+        }
+        if let (Some(filename), Some(mut coverage)) = (matches.value_of("COVERAGE"), coverage) {
+            coverage.add_trace(&result.trace);
+            coverage.save(filename)?;
+        }
+        if should_save(result.is_vm_success) {
+            save_test_state(&output, account, result.state_init)?;
+        }
+        return Ok(());
+    }
+
+    println!("Engine capabilities: {}", capabilities);
+    let result = call_contract(addr, state_init, params)?;
+    if result.is_vm_success && decode_c6 {
+        decode_actions(&result.out_actions, action_decoder)?;
+    }
+    print!("{}", printer::execution_result_printer(&result));
+    if let Some(filename) = matches.value_of("TRACE_OUT") {
+        tracefile::save_trace(filename, &result.trace, matches.is_present("TRACE_STACK"))?;
+        println!("Trace saved to {}", filename);
+    }
+    if let (Some(filename), Some(mut coverage)) = (matches.value_of("COVERAGE"), coverage) {
+        coverage.add_trace(&result.trace);
+        coverage.save(filename)?;
+        println!("{}", coverage.summary());
+    }
+    if matches.is_present("PROFILE") || matches.is_present("PROFILE_OUT") {
+        let profile = profiler::Profile::from_trace(&result.trace);
+        if matches.is_present("PROFILE") {
+            print!("{}", profile.hotspots(PROFILE_TOP));
+        }
+        if let Some(filename) = matches.value_of("PROFILE_OUT") {
+            profile.save_collapsed_stacks(filename)?;
+            println!("Gas profile saved to {}", filename);
+        }
+    }
+    if let (Some(abi_file), true) = (data_abi, result.is_vm_success) {
+        println!(
+            "Data after:\n{}",
+            decode_data(abi_file, result.state_init.data.as_ref())?
+        );
+    }
+    print!(
+        "{}",
+        printer::state_change_printer(&state_before, &result.state_init)
+    );
+    if should_save(result.is_vm_success) {
+        save_test_state(&output, account, result.state_init)?;
+        print_saved_state(&input, &output);
+    }
+
+    println!("TEST COMPLETED");
+    Ok(())
+}
+
+fn run_replay_subcmd(matches: &ArgMatches) -> Status {
+    let account = program::load_account(matches.value_of("ACCOUNT").unwrap())?;
+    let msg_file = matches.value_of("MESSAGE").unwrap();
+    let (mut root_slice, _) = program::load_stateinit(msg_file)?;
+    let message = Message::construct_from(&mut root_slice)?;
+    let mut block_info = parse_block_info(matches)?;
+    block_info.init_code_hash = account.init_code_hash().cloned();
+    let (addr, state_init, balance) =
+        testcall::replay_state(&account, &message, block_info.storage_fee)?;
+
+    let now = match matches.value_of("NOW") {
+        Some(now) => parse_now(Some(now))?,
+        None => match message.int_header() {
+            Some(hdr) => hdr.created_at.as_u32(),
+            None => get_now(),
         },
-    )?;
-    if is_success {
-        save_to_file(state_init, Some(&input), 0, false)?;
+    };
+    let trace_level = parse_trace_level(matches);
+    let debug_info = matches
+        .value_of("DEBUG_MAP")
+        .and_then(testcall::load_debug_info);
+
+    let mut params = TestCallParams::new(MsgInfo {
+        now,
+        message: Some(message),
+        ..Default::default()
+    })
+    .with_balance(&balance)
+    .with_config(testcall::load_config_boc(
+        matches.value_of("CONFIG").unwrap(),
+    )?)
+    .with_real_gas()
+    .with_block_info(block_info)
+    .with_trace_level(trace_level)
+    .with_debug_info(debug_info.clone());
+    if matches.is_present("TRACE_STACK") {
+        params = params.with_trace_stack();
+    } else if matches.is_present("TRACE_OUT") {
+        params = params.with_trace_collection();
+    }
+    if matches.is_present("DEBUG") {
+        println!("Debugger started, type h for help");
+        params = params.with_trace_hook(debugger::Debugger::new(debug_info).into_hook());
+    }
+    testcall::init_logger(trace_level == TraceLevel::Full)?;
+
+    let result = call_contract(addr, state_init, params)?;
+    print!("{}", printer::execution_result_printer(&result));
+    if let Some(filename) = matches.value_of("TRACE_OUT") {
+        tracefile::save_trace(filename, &result.trace, matches.is_present("TRACE_STACK"))?;
+        println!("Trace saved to {}", filename);
+    }
+    Ok(())
+}
+
+/// Saves the new contract state to the TVC file or, if the input is an account boc,
+/// replaces code and data of the account keeping its balance and status.
+fn save_test_state(output: &str, account: Option<Account>, state_init: StateInit) -> Status {
+    match account {
+        Some(mut account) => {
+            if let Some(code) = state_init.code {
+                account.set_code(code);
+            }
+            if let Some(data) = state_init.data {
+                account.set_data(data);
+            }
+            account.update_storage_stat()?;
+            program::save_account(&account, output)
+        }
+        None => save_to_file(state_init, Some(output), 0, false).map(|_| ()),
+    }
+}
+
+fn print_saved_state(input: &str, output: &str) {
+    if input == output {
         println!("Contract persistent data updated");
+    } else {
+        println!("Contract state saved to {}", output);
     }
+}
 
-    println!("TEST COMPLETED");
+fn decode_actions<F>(actions: &OutActions, action_decoder: F) -> Status
+where
+    F: Fn(SliceData, bool),
+{
+    println!("Output actions:\n----------------");
+    for act in actions {
+        match act {
+            OutAction::SendMsg { mode: _, out_msg } => {
+                println!("Action(SendMsg):\n{}", printer::msg_printer(out_msg)?);
+                if let Some(b) = out_msg.body() {
+                    action_decoder(b, out_msg.is_internal());
+                }
+            }
+            OutAction::SetCode { .. } => {
+                println!("Action(SetCode)");
+            }
+            OutAction::ReserveCurrency { .. } => {
+                println!("Action(ReserveCurrency)");
+            }
+            OutAction::ChangeLibrary { .. } => {
+                println!("Action(ChangeLibrary)");
+            }
+            _ => println!("Action(Unknown)"),
+        };
+    }
+    Ok(())
+}
+
+fn parse_block_info(matches: &ArgMatches) -> Result<BlockInfo> {
+    let parse_hash = |name| {
+        matches
+            .value_of(name)
+            .map(|hash| {
+                UInt256::from_str(hash.trim_start_matches("0x"))
+                    .map_err(|e| format_err!("invalid {} value {}: {}", name, hash, e))
+            })
+            .transpose()
+    };
+    let prev_blocks = match matches.value_of("PREV_BLOCKS") {
+        Some(filename) => {
+            let json = std::fs::read_to_string(filename)
+                .map_err(|e| format_err!("failed to read {}: {}", filename, e))?;
+            Some(testcall::prev_blocks_info(&serde_json::from_str(&json)?)?)
+        }
+        None => None,
+    };
+    Ok(BlockInfo {
+        block_lt: matches
+            .value_of("BLOCK_LT")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(0),
+        trans_lt: matches
+            .value_of("TRANS_LT")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(0),
+        rand_seed: parse_hash("RAND_SEED")?.unwrap_or_default(),
+        init_code_hash: parse_hash("INIT_CODE_HASH")?,
+        storage_fee: matches
+            .value_of("STORAGE_FEE")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(0),
+        due_payment: matches
+            .value_of("DUE_PAYMENT")
+            .map(str::parse)
+            .transpose()?,
+        prev_blocks,
+    })
+}
+
+fn parse_trace_level(matches: &ArgMatches) -> TraceLevel {
+    if matches.is_present("TRACE") {
+        TraceLevel::Full
+    } else if matches.is_present("TRACE_MIN") {
+        TraceLevel::Minimal
+    } else {
+        TraceLevel::None
+    }
+}
+
+fn run_get_subcmd(matches: &ArgMatches) -> Status {
+    let input = matches.value_of("INPUT").unwrap();
+    let method = matches.value_of("METHOD").unwrap();
+    let zero_address = "0".repeat(64);
+    let address = matches.value_of("ADDRESS").unwrap_or(&zero_address);
+    let addr = program::parse_address(address)?;
+    let state_init = load_from_file(input)?;
+    let trace_level = parse_trace_level(matches);
+    testcall::init_logger(trace_level == TraceLevel::Full)?;
+
+    let mut params = TestCallParams::new(MsgInfo {
+        now: parse_now(matches.value_of("NOW"))?,
+        ..Default::default()
+    })
+    .with_block_info(parse_block_info(matches)?)
+    .with_trace_level(trace_level)
+    .with_debug_info(
+        matches
+            .value_of("DEBUG_MAP")
+            .and_then(testcall::load_debug_info),
+    );
+    if let Some(balance) = matches.value_of("BALANCE") {
+        params = params.with_balance(balance);
+    }
+    if let Some(config) = matches.value_of("CONFIG").and_then(testcall::load_config) {
+        params = params.with_config(config);
+    }
+
+    match matches.value_of("ABI_JSON") {
+        Some(abi_file) => {
+            let abi_params = match matches.value_of("ABI_PARAMS") {
+                Some(abi_params) if abi_params.find('{').is_none() => {
+                    std::fs::read_to_string(abi_params)
+                        .map_err(|e| format_err!("failed to load params from file: {}", e))?
+                }
+                Some(abi_params) => abi_params.to_owned(),
+                None => "{}".to_owned(),
+            };
+            let body = build_abi_body(
+                abi_file,
+                method,
+                &abi_params,
+                matches.value_of("ABI_HEADER"),
+                None,
+                false,
+                Some(addr.to_string()),
+            )?;
+            params.msg_info.body = Some(SliceData::load_builder(body)?);
+            let result = call_contract(addr, state_init, params)?;
+            print!("{}", printer::execution_result_printer(&result));
+            for msg in result.out_messages.iter().filter(|msg| !msg.is_internal()) {
+                if let Some(body) = msg.body() {
+                    println!("Output: {}", decode_body(abi_file, method, body, false)?);
+                }
+            }
+        }
+        None => {
+            let method_id = method
+                .parse::<u32>()
+                .unwrap_or_else(|_| program::get_method_id(method));
+            let args = matches
+                .values_of("ARGS")
+                .map(|args| args.map(testcall::parse_stack_item).collect())
+                .transpose()?
+                .unwrap_or_default();
+            println!("Method id: {}", method_id);
+            let result = testcall::run_get_method(addr, state_init, method_id, args, params)?;
+            print!("{}", printer::execution_result_printer(&result));
+        }
+    }
+    Ok(())
+}
+
+fn run_genkey_subcmd(matches: &ArgMatches) -> Status {
+    let output = matches.value_of("OUTPUT").unwrap();
+    let overwrite = matches.is_present("FORCE");
+    if !overwrite && std::path::Path::new(output).exists() {
+        bail!(
+            "key file {} already exists, use --force to overwrite it",
+            output
+        );
+    }
+    let path = matches.value_of("PATH").unwrap_or(keyman::DEFAULT_HD_PATH);
+    let phrase = match matches.value_of("PHRASE") {
+        Some(phrase) => Some(phrase.to_owned()),
+        None if matches.is_present("GEN_PHRASE") => {
+            let word_count = matches
+                .value_of("WORDS")
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or(keyman::DEFAULT_WORD_COUNT);
+            let phrase = keyman::generate_mnemonic(word_count)?;
+            println!("Seed phrase: {}", phrase);
+            Some(phrase)
+        }
+        None => None,
+    };
+    if phrase.is_none() && matches.is_present("PATH") {
+        bail!("--path is used only with --phrase or --gen-phrase")
+    }
+    let pair = match (phrase, matches.value_of("SEED")) {
+        (Some(phrase), _) => Keypair::from_mnemonic(&phrase, path)?,
+        (None, Some(seed)) => Keypair::from_seed(seed)?,
+        (None, None) => Keypair::generate()?,
+    };
+    match matches.is_present("ENCRYPT") {
+        true => pair.save_encrypted(output, &keyman::read_new_password()?, overwrite)?,
+        false => pair.save(output, overwrite)?,
+    }
+    println!("Public key: {}", hex::encode(pair.public.to_bytes()));
+    println!("Keypair saved to {}", output);
+    Ok(())
+}
+
+fn run_address_subcmd(matches: &ArgMatches) -> Status {
+    let input = matches.value_of("INPUT").unwrap();
+    let wc = match matches.value_of("WORKCHAIN") {
+        Some(w) => w.parse::<i8>()?,
+        None => 0,
+    };
+    let address = if matches.is_present("TVC") {
+        let hash = load_from_file(input)?.hash()?;
+        MsgAddressInt::with_standart(None, wc, hash.into())?
+    } else if let Ok(account_id) = AccountId::from_str(input) {
+        MsgAddressInt::with_standart(None, wc, account_id)?
+    } else if input.contains(':') {
+        program::parse_address(input)?
+    } else {
+        let (address, bounce, testnet) = program::parse_userfriendly_address(input)?;
+        println!("Bounceable: {}", bounce);
+        println!("Testnet: {}", testnet);
+        address
+    };
+    let user_friendly = program::calc_userfriendly_address(
+        address.workchain_id() as i8,
+        &address.address().get_bytestring(0),
+        !matches.is_present("NON_BOUNCEABLE"),
+        matches.is_present("TESTNET"),
+    );
+    println!("Raw: {}", address);
+    println!("User-friendly: {}", user_friendly);
+    println!(
+        "User-friendly (url-safe): {}",
+        program::to_url_safe_address(&user_friendly)
+    );
+    Ok(())
+}
+
+fn run_trace_diff_subcmd(matches: &ArgMatches) -> Status {
+    let first = tracefile::load_trace(matches.value_of("FIRST").unwrap())?;
+    let second = tracefile::load_trace(matches.value_of("SECOND").unwrap())?;
+    let context = matches
+        .value_of("CONTEXT")
+        .map(|v| v.parse::<usize>())
+        .transpose()?
+        .unwrap_or(5);
+    print!("{}", tracefile::diff_report(&first, &second, context));
+    Ok(())
+}
+
+fn run_scenario_subcmd(matches: &ArgMatches) -> Status {
+    let trace_level = parse_trace_level(matches);
+    testcall::init_logger(trace_level == TraceLevel::Full)?;
+    println!("SCENARIO STARTED");
+    let state_init = scenario::run_scenario(
+        matches.value_of("INPUT").unwrap(),
+        trace_level,
+        matches.value_of("COVERAGE"),
+    )?;
+    if let Some(output) = matches.value_of("OUTPUT") {
+        save_to_file(state_init, Some(output), 0, false)?;
+        println!("Contract state saved to {}", output);
+    }
+    println!("SCENARIO COMPLETED");
+    Ok(())
+}
+
+fn run_sandbox_subcmd(matches: &ArgMatches) -> Status {
+    let config = sandbox::load_sandbox_config(matches.value_of("INPUT").unwrap())?;
+    let max_messages = matches
+        .value_of("MAX_MESSAGES")
+        .map(|v| v.parse::<usize>())
+        .transpose()?
+        .unwrap_or(1000);
+    let trace_level = parse_trace_level(matches);
+    testcall::init_logger(trace_level == TraceLevel::Full)?;
+    let mut sandbox = sandbox::Sandbox::new(&config, trace_level)?;
+    println!("SANDBOX STARTED");
+    let count = sandbox.run(max_messages)?;
+    println!("Delivered messages: {}", count);
+    sandbox.print_balances();
+    if matches.is_present("SAVE") {
+        sandbox.save()?;
+        println!("Account states saved");
+    }
+    println!("SANDBOX COMPLETED");
     Ok(())
 }
 
@@ -354,10 +1233,7 @@ fn build_body(matches: &ArgMatches, address: Option<String>) -> Result<Option<Sl
     let params = matches.value_of("ABI_PARAMS");
     let header = matches.value_of("ABI_HEADER");
     if mask == 0x3 {
-        let key_file = matches
-            .value_of("SIGN")
-            .map(Keypair::from_file)
-            .transpose()?;
+        let signer = matches.value_of("SIGN").map(load_signer).transpose()?;
         let params = params.map_or(Ok("{}".to_owned()), |params| {
             if params.find('{').is_none() {
                 std::fs::read_to_string(params)
@@ -372,7 +1248,7 @@ fn build_body(matches: &ArgMatches, address: Option<String>) -> Result<Option<Sl
             method_name.unwrap(),
             &params,
             header,
-            key_file,
+            signer.as_deref(),
             is_internal,
             address,
         )?;
@@ -385,41 +1261,97 @@ fn build_body(matches: &ArgMatches, address: Option<String>) -> Result<Option<Sl
     }
 }
 
+/// Returns the contract name (the hex account id used in file names) and the destination
+/// address of the message. A plain hex INPUT is placed into the workchain given by the option,
+/// raw `wc:hex` and user-friendly addresses carry their own workchain.
+fn message_destination(input: &str, wc: Option<&str>) -> Result<(String, MsgAddressInt)> {
+    if let Ok(account_id) = AccountId::from_str(input) {
+        let wc = match wc {
+            Some(w) => w.parse::<i8>()?,
+            None => 0,
+        };
+        let address = MsgAddressInt::with_standart(None, wc, account_id)?;
+        return Ok((input.to_owned(), address));
+    }
+    let address = program::parse_address(input)?;
+    Ok((address.address().to_hex_string(), address))
+}
+
+/// Headers of an internal message set by the `message` subcommand options.
+#[derive(Default)]
+struct InternalHeaderArgs<'a> {
+    value: &'a str,
+    src: Option<&'a str>,
+    bounce: Option<&'a str>,
+    created_lt: Option<&'a str>,
+    created_at: Option<&'a str>,
+}
+
+fn internal_message(dest_address: MsgAddressInt, args: &InternalHeaderArgs) -> Result<Message> {
+    let source_address = match args.src {
+        Some(src) => program::parse_address(src)?,
+        None => {
+            MsgAddressInt::with_standart(None, -1, AccountId::from_str("55".repeat(32).as_str())?)?
+        }
+    };
+    let mut header = InternalMessageHeader {
+        ihr_disabled: true,
+        bounce: args
+            .bounce
+            .map(|v| v.parse::<bool>())
+            .transpose()
+            .map_err(|e| format_err!("invalid bounce flag: {}", e))?
+            .unwrap_or(true),
+        src: MsgAddressIntOrNone::Some(source_address),
+        dst: dest_address,
+        value: testcall::decode_balance(Some(args.value))?.1,
+        ..Default::default()
+    };
+    if let Some(created_lt) = args.created_lt {
+        header.created_lt = created_lt.parse::<u64>()?;
+    }
+    if let Some(created_at) = args.created_at {
+        header.created_at = created_at.parse::<u32>()?.into();
+    }
+    Ok(Message::with_int_header(header))
+}
+
+fn message_file_name(output: Option<&str>, address_str: &str, suffix: &str) -> String {
+    match output {
+        Some(output) => output.to_owned(),
+        None => address_str.get(0..8).unwrap_or("00000000").to_string() + suffix,
+    }
+}
+
 fn build_message(
+    matches: &ArgMatches,
     address_str: &str,
-    wc: Option<&str>,
+    dest_address: MsgAddressInt,
     body: Option<SliceData>,
-    pack_code: bool,
     suffix: &str,
-    internal: bool,
 ) -> Status {
-    let wc = match wc {
-        Some(w) => w.parse::<i8>()?,
-        None => -1,
-    };
-    println!("contract address {}", address_str);
-    let dest_address = MsgAddressInt::with_standart(None, wc, AccountId::from_str(address_str)?)?;
-
-    let mut msg = if internal {
-        let source_address = MsgAddressIntOrNone::Some(MsgAddressInt::with_standart(
-            None,
-            -1,
-            AccountId::from_str("55".repeat(32).as_str())?,
-        )?);
-        Message::with_int_header(InternalMessageHeader {
-            ihr_disabled: true,
-            bounce: true,
-            src: source_address,
-            dst: dest_address,
-            ..Default::default()
-        })
-    } else {
-        Message::with_ext_in_header(ExternalInboundMessageHeader {
+    let stdout_format = matches.value_of("STDOUT");
+    if stdout_format.is_none() {
+        println!("contract address {}", dest_address);
+    }
+
+    let mut msg = match matches.value_of("INTERNAL") {
+        Some(value) => {
+            let args = InternalHeaderArgs {
+                value,
+                src: matches.value_of("SRC"),
+                bounce: matches.value_of("BOUNCE"),
+                created_lt: matches.value_of("CREATED_LT"),
+                created_at: matches.value_of("CREATED_AT"),
+            };
+            internal_message(dest_address, &args)?
+        }
+        None => Message::with_ext_in_header(ExternalInboundMessageHeader {
             dst: dest_address,
             ..Default::default()
-        })
+        }),
     };
-    if pack_code {
+    if matches.is_present("INIT") {
         msg.set_state_init(load_from_file(&format!("{}.tvc", address_str))?);
     }
     if let Some(body) = body {
@@ -429,13 +1361,157 @@ fn build_message(
     let root_cell = msg.serialize()?;
     let mut bytes = Vec::new();
     BocWriter::with_root(&root_cell)?.write_ex(&mut bytes, false, true, None, Some(4))?;
+    let hash = root_cell.repr_hash();
 
-    println!("Encoded msg: {}", hex::encode(&bytes));
+    match stdout_format {
+        Some(format @ ("base64" | "hex")) => {
+            // stdout carries only the boc, the hash goes to stderr to track the message
+            eprintln!("Message hash: {:x}", hash);
+            if format == "base64" {
+                println!("{}", base64::encode(&bytes));
+            } else {
+                println!("{}", hex::encode(&bytes));
+            }
+        }
+        Some(_) => println!(
+            "{:#}",
+            serde_json::json!({
+                "id": format!("{:x}", hash),
+                "boc": base64::encode(&bytes),
+            })
+        ),
+        None => {
+            println!("Encoded msg: {}", hex::encode(&bytes));
+            println!("Message hash: {:x}", hash);
 
-    let output_file_name = address_str.get(0..8).unwrap_or("00000000").to_string() + suffix;
-    let mut f = File::create(&output_file_name)?;
-    f.write_all(&bytes)?;
+            let output_file_name =
+                message_file_name(matches.value_of("OUTPUT"), address_str, suffix);
+            let mut f = File::create(&output_file_name)?;
+            f.write_all(&bytes)?;
 
-    println!("boc file created: {}", output_file_name);
+            println!("boc file created: {}", output_file_name);
+        }
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_internal_message() {
+        let (name, dest) = message_destination(&"11".repeat(32), None).unwrap();
+        assert_eq!(dest.workchain_id(), 0);
+        let src = format!("0:{}", "22".repeat(32));
+        let args = InternalHeaderArgs {
+            value: "1000",
+            src: Some(&src),
+            bounce: Some("false"),
+            created_lt: Some("5"),
+            created_at: Some("1700000000"),
+        };
+        let msg = internal_message(dest.clone(), &args).unwrap();
+        let header = msg.int_header().unwrap();
+        assert_eq!(header.value.grams.as_u128(), 1000);
+        assert_eq!(
+            header.src,
+            MsgAddressIntOrNone::Some(MsgAddressInt::from_str(&src).unwrap())
+        );
+        assert_eq!(header.dst, dest);
+        assert!(!header.bounce);
+        assert_eq!(header.created_lt, 5);
+        assert_eq!(header.created_at.as_u32(), 1700000000);
+
+        let args = InternalHeaderArgs {
+            value: "1000",
+            ..Default::default()
+        };
+        let msg = internal_message(dest, &args).unwrap();
+        let header = msg.int_header().unwrap();
+        assert!(header.bounce);
+        assert_eq!(msg.src().unwrap().workchain_id(), -1);
+
+        assert_eq!(
+            message_file_name(None, &name, "-msg-body.boc"),
+            "11111111-msg-body.boc"
+        );
+        assert_eq!(
+            message_file_name(Some("out.boc"), &name, "-msg-body.boc"),
+            "out.boc"
+        );
+    }
+
+    fn run_compile(args: &[&str]) -> Status {
+        let matches = linker_app("test").get_matches_from_safe(args)?;
+        run_compile_subcmd(matches.subcommand_matches("compile").unwrap())
+    }
+
+    #[test]
+    fn test_compile_subcmd() {
+        let dir = std::env::temp_dir();
+        let name = |suffix: &str| {
+            dir.join(format!(
+                "tvm_linker_test_compile_{}{}",
+                std::process::id(),
+                suffix
+            ))
+            .to_str()
+            .unwrap()
+            .to_string()
+        };
+        let (source, abi, tvc) = (name(".code"), name(".abi.json"), name(".tvc"));
+        std::fs::write(&source, "PUSHINT 1\nDROP\n").unwrap();
+        let abi_json = serde_json::json!({
+            "ABI version": 2,
+            "version": "2.4",
+            "functions": [],
+            "events": [],
+            "fields": [
+                {"name": "_pubkey", "type": "uint256", "init": true},
+                {"name": "owner", "type": "uint32", "init": true},
+            ],
+        });
+        std::fs::write(&abi, abi_json.to_string()).unwrap();
+
+        let args: &[&str] = &[
+            "tvm_linker",
+            "compile",
+            &source,
+            "--abi-json",
+            &abi,
+            "-o",
+            &tvc,
+            "--silent",
+        ];
+        run_compile(args).unwrap();
+        let state_init = load_from_file(&tvc).unwrap();
+        assert_eq!(state_init.data, Some(build_initial_data(&abi).unwrap()));
+        // the debug map is named after the tvc file by default
+        let debug_map = name(".debug.json");
+        let debug_info = testcall::load_debug_info(&debug_map).unwrap();
+        let code = state_init.code.unwrap();
+        assert!(debug_info.get(&code.repr_hash()).is_some());
+        std::fs::remove_file(&debug_map).unwrap();
+
+        let custom_map = name(".map.json");
+        let args: &[&str] = &[
+            "tvm_linker",
+            "compile",
+            &source,
+            "-o",
+            &tvc,
+            "--debug-map",
+            &custom_map,
+            "--silent",
+        ];
+        run_compile(args).unwrap();
+        assert_eq!(load_from_file(&tvc).unwrap().data, Some(Cell::default()));
+        assert!(testcall::load_debug_info(&custom_map).is_some());
+        assert!(!std::path::Path::new(&debug_map).exists());
+
+        for file in [source, abi, tvc, custom_map] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+}
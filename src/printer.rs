@@ -14,6 +14,10 @@ use anyhow::format_err;
 use ever_block::write_boc;
 use ever_block::*;
 use ever_block::{BuilderData, Cell, Result};
+use ever_vm::stack::StackItem;
+use serde_json::{json, Value};
+
+use crate::testcall::ExecutionResult;
 
 fn get_version(root: &Cell) -> Result<String> {
     let cell1 = root
@@ -94,6 +98,155 @@ pub fn msg_printer(msg: &Message) -> Result<String> {
     ))
 }
 
+pub fn transaction_printer(transaction: &Transaction) -> Result<String> {
+    let mut result = format!(
+        "Transaction {}\n lt: {}\n now: {}\n",
+        transaction.hash()?.to_hex_string(),
+        transaction.logical_time(),
+        transaction.now(),
+    );
+    match transaction.read_description()? {
+        TransactionDescr::Ordinary(descr) => {
+            result += &format!(" credit_first: {}\n", descr.credit_first);
+            result += &format!(" storage_phase: {}\n", print_phase(&descr.storage_ph));
+            result += &format!(" credit_phase: {}\n", print_phase(&descr.credit_ph));
+            result += &format!(" compute_phase: {:?}\n", descr.compute_ph);
+            result += &format!(" action_phase: {}\n", print_phase(&descr.action));
+            result += &format!(" bounce_phase: {}\n", print_phase(&descr.bounce));
+            result += &format!(" aborted: {}\n", descr.aborted);
+            result += &format!(" destroyed: {}\n", descr.destroyed);
+        }
+        TransactionDescr::TickTock(descr) => {
+            result += &format!(" tick_tock: {:?}\n", descr.tt);
+            result += &format!(" storage_phase: {:?}\n", descr.storage);
+            result += &format!(" compute_phase: {:?}\n", descr.compute_ph);
+            result += &format!(" action_phase: {}\n", print_phase(&descr.action));
+            result += &format!(" aborted: {}\n", descr.aborted);
+            result += &format!(" destroyed: {}\n", descr.destroyed);
+        }
+        descr => result += &format!(" description: {:?}\n", descr),
+    }
+    result += &format!(" total_fees: {}\n", print_cc(transaction.total_fees()));
+    result += &format!(" out_msgs: {}\n", transaction.outmsg_cnt);
+    Ok(result)
+}
+
+pub fn stack_printer(title: &str, stack: &[StackItem]) -> String {
+    let mut result = format!("--- {} ------------------------\n", title);
+    for item in stack {
+        result += &format!("{}\n", item);
+    }
+    result += "----------------------------------------\n";
+    result
+}
+
+pub fn execution_result_printer(result: &ExecutionResult) -> String {
+    let mut output = String::new();
+    if let Some(exception) = &result.exception {
+        output += &format!("Unhandled exception: {}\n", exception);
+    }
+    output += &format!("TVM terminated with exit code {}\n", result.exit_code);
+    output += &format!("Computing phase is success: {}\n", result.is_vm_success);
+    output += &format!("Gas used: {}\n\n", result.gas_used);
+    output += &stack_printer("Post-execution stack state", &result.stack);
+    output += &format!("{}\n", result.ctrls_dump);
+    output
+}
+
+pub fn stack_item_json(item: &StackItem) -> Value {
+    match item {
+        StackItem::None => json!({ "type": "null" }),
+        StackItem::Integer(value) => json!({ "type": "int", "value": value.to_string() }),
+        StackItem::Cell(cell) => json!({
+            "type": "cell",
+            "value": tree_of_cells_into_base64(Some(cell)),
+        }),
+        StackItem::Slice(slice) => json!({
+            "type": "slice",
+            "value": tree_of_cells_into_base64(Some(&slice.clone().into_cell())),
+        }),
+        StackItem::Builder(builder) => json!({
+            "type": "builder",
+            "value": tree_of_cells_into_base64(builder.as_ref().clone().into_cell().ok().as_ref()),
+        }),
+        StackItem::Tuple(items) => json!({
+            "type": "tuple",
+            "value": items.iter().map(stack_item_json).collect::<Vec<_>>(),
+        }),
+        StackItem::Continuation(_) => json!({ "type": "continuation" }),
+    }
+}
+
+/// Builds the machine-readable report of the execution,
+/// message bodies are decoded by the supplied decoder.
+pub fn execution_result_json<F>(result: &ExecutionResult, decoder: F) -> Result<Value>
+where
+    F: Fn(SliceData, bool) -> Option<Value>,
+{
+    let mut out_messages = Vec::new();
+    for msg in &result.out_messages {
+        out_messages.push(json!({
+            "boc": base64::encode(write_boc(&msg.serialize()?)?),
+            "decoded_body": msg.body().and_then(|body| decoder(body, msg.is_internal())),
+        }));
+    }
+    Ok(json!({
+        "exit_code": result.exit_code,
+        "exception": result.exception,
+        "committed": result.is_vm_success,
+        "gas_used": result.gas_used,
+        "gas_limit": result.gas_limit,
+        "stack": result.stack.iter().map(stack_item_json).collect::<Vec<_>>(),
+        "out_messages": out_messages,
+        "data_hash": result
+            .state_init
+            .data
+            .as_ref()
+            .map(|data| data.repr_hash().to_hex_string()),
+        "failed_position": if result.is_vm_success {
+            None
+        } else {
+            result.last_position.clone()
+        },
+    }))
+}
+
+fn print_phase<T: std::fmt::Debug>(phase: &Option<T>) -> String {
+    phase
+        .as_ref()
+        .map_or("None".to_string(), |phase| format!("{:?}", phase))
+}
+
+pub fn account_balance_printer(account: &Account) -> String {
+    account.balance().map_or("None".to_string(), print_cc)
+}
+
+/// Describes changes of the contract state made by the execution: data and code hashes.
+pub fn state_change_printer(before: &StateInit, after: &StateInit) -> String {
+    let hash = |cell: Option<&Cell>| {
+        cell.map_or("None".to_string(), |cell| cell.repr_hash().to_hex_string())
+    };
+    let mut result = String::new();
+    let (data_before, data_after) = (hash(before.data.as_ref()), hash(after.data.as_ref()));
+    if data_before == data_after {
+        result += &format!("Data hash: {} (unchanged)\n", data_before);
+    } else {
+        result += &format!("Data hash: {} -> {}\n", data_before, data_after);
+    }
+    let (code_before, code_after) = (hash(before.code.as_ref()), hash(after.code.as_ref()));
+    if code_before == code_after {
+        result += &format!("Code hash: {} (unchanged)\n", code_before);
+    } else if before.code.is_none() {
+        result += &format!("Code hash: {} -> {} (deployed)\n", code_before, code_after);
+    } else {
+        result += &format!(
+            "Code hash: {} -> {} (replaced by SetCode)\n",
+            code_before, code_after
+        );
+    }
+    result
+}
+
 fn print_msg_header(header: &CommonMsgInfo) -> String {
     match header {
         CommonMsgInfo::IntMsgInfo(header) => {
@@ -142,6 +295,18 @@ fn print_cc(cc: &CurrencyCollection) -> String {
     result
 }
 
+#[test]
+fn check_stack_item_json() {
+    let item = StackItem::tuple(vec![StackItem::int(5), StackItem::None]);
+    assert_eq!(
+        stack_item_json(&item),
+        json!({
+            "type": "tuple",
+            "value": [ { "type": "int", "value": "5" }, { "type": "null" } ],
+        })
+    );
+}
+
 #[test]
 fn check_output_for_money() {
     let mut cc = CurrencyCollection::with_grams(u64::MAX >> 8);
@@ -161,3 +326,43 @@ fn check_output_for_money() {
         r#"72057594037927935 other: { "12": "125", "17": "340282366920938463463374607431768213356", "4294967295": "452312848583266388373324160190187140051835877600158453279131187530910662655" }"#
     );
 }
+
+#[test]
+fn check_state_change_printer() {
+    let cell = |byte| {
+        BuilderData::with_raw(vec![byte], 8)
+            .unwrap()
+            .into_cell()
+            .unwrap()
+    };
+    let before = StateInit {
+        code: Some(cell(1)),
+        data: Some(cell(2)),
+        ..Default::default()
+    };
+    let code_hash = cell(1).repr_hash().to_hex_string();
+    let data_hash = cell(2).repr_hash().to_hex_string();
+    assert_eq!(
+        state_change_printer(&before, &before),
+        format!(
+            "Data hash: {} (unchanged)\nCode hash: {} (unchanged)\n",
+            data_hash, code_hash
+        )
+    );
+    let after = StateInit {
+        code: Some(cell(3)),
+        data: Some(cell(4)),
+        ..Default::default()
+    };
+    assert_eq!(
+        state_change_printer(&before, &after),
+        format!(
+            "Data hash: {} -> {}\nCode hash: {} -> {} (replaced by SetCode)\n",
+            data_hash,
+            cell(4).repr_hash().to_hex_string(),
+            code_hash,
+            cell(3).repr_hash().to_hex_string()
+        )
+    );
+    assert!(state_change_printer(&StateInit::default(), &after).ends_with(" (deployed)\n"));
+}
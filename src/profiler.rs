@@ -0,0 +1,239 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::format_err;
+use ever_assembler::DbgPos;
+use ever_block::Status;
+use regex::Regex;
+
+use crate::testcall::TraceEvent;
+
+const UNKNOWN_FUNCTION: &str = "<unknown>";
+
+#[derive(Clone, Copy, Default)]
+pub struct Stat {
+    pub gas: i64,
+    pub count: u64,
+}
+
+impl Stat {
+    fn add(&mut self, gas: i64) {
+        self.gas += gas;
+        self.count += 1;
+    }
+}
+
+/// Resolves the enclosing function of a source line by the nearest preceding
+/// function directive or label in the source file.
+#[derive(Default)]
+struct SourceFunctions {
+    files: HashMap<String, Vec<Option<String>>>,
+}
+
+impl SourceFunctions {
+    fn parse(source: &str) -> Vec<Option<String>> {
+        let directive =
+            Regex::new(r"^\s*\.(macro|globl|internal|fragment|function|public)\s+:?([\w$.@-]+)")
+                .unwrap();
+        let label = Regex::new(r"^([A-Za-z_$][\w$.@-]*):\s*$").unwrap();
+        let mut current = None;
+        source
+            .lines()
+            .map(|line| {
+                if let Some(captures) = directive.captures(line) {
+                    current = Some(captures[2].to_string());
+                } else if let Some(captures) = label.captures(line) {
+                    current = Some(captures[1].to_string());
+                }
+                current.clone()
+            })
+            .collect()
+    }
+
+    fn function_at(&mut self, pos: &DbgPos) -> String {
+        let lines = self.files.entry(pos.filename.clone()).or_insert_with(|| {
+            std::fs::read_to_string(&pos.filename)
+                .map(|source| Self::parse(&source))
+                .unwrap_or_default()
+        });
+        // debug map lines are 1-based
+        pos.line
+            .checked_sub(1)
+            .and_then(|index| lines.get(index).cloned().flatten())
+            .unwrap_or_else(|| pos.filename.clone())
+    }
+}
+
+/// Gas and instruction count aggregated by source line, by function and by call stack.
+#[derive(Default)]
+pub struct Profile {
+    pub total: Stat,
+    pub lines: BTreeMap<String, Stat>,
+    pub functions: BTreeMap<String, Stat>,
+    pub stacks: BTreeMap<String, i64>,
+}
+
+impl Profile {
+    pub fn from_trace(trace: &[TraceEvent]) -> Self {
+        Self::build(trace, &mut SourceFunctions::default())
+    }
+
+    fn build(trace: &[TraceEvent], sources: &mut SourceFunctions) -> Self {
+        let mut profile = Profile::default();
+        let mut stack: Vec<String> = Vec::new();
+        let mut prev_is_call = false;
+        for event in trace {
+            let (location, function) = match &event.position {
+                Some(pos) => (
+                    format!("{}:{}", pos.filename, pos.line),
+                    sources.function_at(pos),
+                ),
+                None => (
+                    format!("{:x}:{}", event.cell_hash, event.offset),
+                    UNKNOWN_FUNCTION.to_string(),
+                ),
+            };
+            // a function entered by a call instruction is nested into the caller,
+            // returning to a function on the stack drops the callees
+            if stack.last() != Some(&function) {
+                if let Some(index) = stack.iter().rposition(|name| name == &function) {
+                    stack.truncate(index + 1);
+                } else if prev_is_call || stack.is_empty() {
+                    stack.push(function.clone());
+                } else {
+                    *stack.last_mut().unwrap() = function.clone();
+                }
+            }
+            prev_is_call = event.cmd_str.starts_with("CALL");
+
+            profile.total.add(event.gas_cmd);
+            profile
+                .lines
+                .entry(location)
+                .or_default()
+                .add(event.gas_cmd);
+            profile
+                .functions
+                .entry(function)
+                .or_default()
+                .add(event.gas_cmd);
+            *profile.stacks.entry(stack.join(";")).or_default() += event.gas_cmd;
+        }
+        profile
+    }
+
+    fn table(title: &str, stats: &BTreeMap<String, Stat>, total: i64, top: usize) -> String {
+        let mut sorted: Vec<_> = stats.iter().collect();
+        sorted.sort_by(|a, b| b.1.gas.cmp(&a.1.gas).then(a.0.cmp(b.0)));
+        let mut result = format!("{}\n", title);
+        result += &format!("{:>10} {:>7} {:>8}  {}\n", "gas", "%", "count", "location");
+        for (name, stat) in sorted.into_iter().take(top) {
+            let percent = if total > 0 {
+                stat.gas as f64 * 100.0 / total as f64
+            } else {
+                0.0
+            };
+            result += &format!(
+                "{:>10} {:>6.2}% {:>8}  {}\n",
+                stat.gas, percent, stat.count, name
+            );
+        }
+        result
+    }
+
+    /// Prints the hotspot tables sorted by gas, at most `top` rows each.
+    pub fn hotspots(&self, top: usize) -> String {
+        format!(
+            "Total gas: {}, instructions: {}\n\n{}\n{}",
+            self.total.gas,
+            self.total.count,
+            Self::table("Gas by source line:", &self.lines, self.total.gas, top),
+            Self::table("Gas by function:", &self.functions, self.total.gas, top),
+        )
+    }
+
+    /// Returns call stacks in the collapsed format used by flamegraph tools.
+    pub fn collapsed_stacks(&self) -> String {
+        self.stacks
+            .iter()
+            .map(|(stack, gas)| format!("{} {}\n", stack, gas))
+            .collect()
+    }
+
+    pub fn save_collapsed_stacks(&self, filename: &str) -> Status {
+        std::fs::write(filename, self.collapsed_stacks())
+            .map_err(|e| format_err!("failed to save profile to {}: {}", filename, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ever_block::UInt256;
+
+    fn event(cmd_str: &str, gas: i64, line: usize) -> TraceEvent {
+        TraceEvent {
+            step: 0,
+            cmd_str: cmd_str.to_string(),
+            gas_used: 0,
+            gas_cmd: gas,
+            cell_hash: UInt256::default(),
+            offset: 0,
+            position: Some(DbgPos {
+                filename: "profile_test.code".to_string(),
+                line,
+            }),
+            stack: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_functions() {
+        let lines = SourceFunctions::parse(
+            "\t.globl\tmain\n\tPUSHINT 1\n\t.macro helper\n\tADD\n_loop:\n\tDROP\n",
+        );
+        assert_eq!(lines[1].as_deref(), Some("main"));
+        assert_eq!(lines[3].as_deref(), Some("helper"));
+        assert_eq!(lines[5].as_deref(), Some("_loop"));
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut sources = SourceFunctions::default();
+        sources.files.insert(
+            "profile_test.code".to_string(),
+            SourceFunctions::parse(".globl main\nPUSHINT 1\nCALLREF\n.macro inc\nINC\nRET\n"),
+        );
+        let trace = vec![
+            event("PUSHINT 1", 18, 2),
+            event("CALLREF", 126, 3),
+            event("INC", 18, 5),
+            event("RET", 5, 6),
+            event("PUSHINT 1", 18, 2),
+        ];
+        let profile = Profile::from_trace(&trace);
+        assert_eq!(profile.total.gas, 185);
+        assert_eq!(profile.total.count, 5);
+        let line = profile.lines["profile_test.code:2"];
+        assert_eq!((line.gas, line.count), (36, 2));
+        // source file does not exist, functions fall back to the file name
+        assert_eq!(profile.functions["profile_test.code"].gas, 185);
+
+        let profile = Profile::build(&trace, &mut sources);
+        assert_eq!(profile.functions["main"].gas, 162);
+        assert_eq!(profile.functions["inc"].count, 2);
+        assert_eq!(profile.collapsed_stacks(), "main 162\nmain;inc 23\n");
+        assert!(profile.hotspots(1).contains("Gas by function:"));
+    }
+}
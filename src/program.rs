@@ -10,11 +10,14 @@
  * See the License for the specific EVERX DEV software governing permissions and
  * limitations under the License.
  */
+use anyhow::{bail, format_err};
 use base64::encode;
 
 use std::fs::File;
 use std::io::{Read, Write};
+use std::str::FromStr;
 
+use ever_assembler::{compile_code_debuggable, DbgInfo, Line, Lines};
 use ever_block::*;
 use std::time::SystemTime;
 
@@ -65,7 +68,8 @@ pub fn save_to_file(state: StateInit, name: Option<&str>, wc: i8, silent: bool)
     Ok(file_name)
 }
 
-fn calc_userfriendly_address(wc: i8, addr: &[u8], bounce: bool, testnet: bool) -> String {
+/// Encodes the address in the user-friendly base64 form with the flags tag and CRC16.
+pub fn calc_userfriendly_address(wc: i8, addr: &[u8], bounce: bool, testnet: bool) -> String {
     let mut bytes: Vec<u8> = vec![];
     bytes.push(if bounce { 0x11 } else { 0x51 } + if testnet { 0x80 } else { 0 });
     bytes.push(wc as u8);
@@ -75,6 +79,52 @@ fn calc_userfriendly_address(wc: i8, addr: &[u8], bounce: bool, testnet: bool) -
     encode(&bytes)
 }
 
+/// Converts the user-friendly address to the url-safe base64 alphabet.
+pub fn to_url_safe_address(address: &str) -> String {
+    address.replace('+', "-").replace('/', "_")
+}
+
+/// Decodes the user-friendly address (base64 or base64url) checking its CRC16.
+/// Returns the address with its bounce and testnet flags.
+pub fn parse_userfriendly_address(address: &str) -> Result<(MsgAddressInt, bool, bool)> {
+    let bytes = base64::decode(address.replace('-', "+").replace('_', "/"))
+        .map_err(|e| format_err!("invalid user-friendly address {}: {}", address, e))?;
+    if bytes.len() != 36 {
+        bail!(
+            "invalid user-friendly address {}: length must be 36 bytes",
+            address
+        );
+    }
+    let crc = XMODEM.checksum(&bytes[..34]);
+    if crc.to_be_bytes() != bytes[34..] {
+        bail!("invalid user-friendly address {}: CRC mismatch", address);
+    }
+    let testnet = bytes[0] & 0x80 != 0;
+    let bounce = match bytes[0] & 0x7F {
+        0x11 => true,
+        0x51 => false,
+        tag => bail!(
+            "invalid user-friendly address {}: unknown tag {:#x}",
+            address,
+            tag
+        ),
+    };
+    let address = MsgAddressInt::with_standart(
+        None,
+        bytes[1] as i8,
+        AccountId::from(UInt256::from_slice(&bytes[2..34])),
+    )?;
+    Ok((address, bounce, testnet))
+}
+
+/// Parses the address given in the raw (`wc:hex` or `hex`) or user-friendly form.
+pub fn parse_address(address: &str) -> Result<MsgAddressInt> {
+    if address.len() == 48 && !address.contains(':') {
+        return Ok(parse_userfriendly_address(address)?.0);
+    }
+    MsgAddressInt::from_str(address).map_err(|e| format_err!("invalid address {}: {}", address, e))
+}
+
 pub fn load_from_file(contract_file: &str) -> Result<StateInit> {
     let mut cell = read_boc(std::fs::read(contract_file)?)?.roots.remove(0);
     // try appending a dummy library cell if there is no such cell in the tvc file
@@ -86,6 +136,20 @@ pub fn load_from_file(contract_file: &str) -> Result<StateInit> {
     StateInit::construct_from_cell(cell)
 }
 
+/// Loads the full account state (balance, status and StateInit) exported from the network.
+pub fn load_account(file_name: &str) -> Result<Account> {
+    let bytes = std::fs::read(file_name)
+        .map_err(|e| format_err!("failed to read account file {}: {}", file_name, e))?;
+    Account::construct_from_bytes(&bytes)
+        .map_err(|e| format_err!("failed to load account from {}: {}", file_name, e))
+}
+
+pub fn save_account(account: &Account, file_name: &str) -> Status {
+    let bytes = account.write_to_bytes()?;
+    std::fs::write(file_name, bytes)
+        .map_err(|e| format_err!("failed to save account to {}: {}", file_name, e))
+}
+
 pub fn load_stateinit(file_name: &str) -> Result<(SliceData, Vec<u8>)> {
     let mut orig_bytes = Vec::new();
     let mut f = File::open(file_name)?;
@@ -101,6 +165,54 @@ pub fn load_stateinit(file_name: &str) -> Result<(SliceData, Vec<u8>)> {
     Ok((SliceData::load_cell(root)?, orig_bytes))
 }
 
+fn load_source_lines(filename: &str, lines: &mut Lines) -> Status {
+    let source = std::fs::read_to_string(filename)
+        .map_err(|e| format_err!("failed to read source file {}: {}", filename, e))?;
+    for (index, text) in source.lines().enumerate() {
+        lines.push(Line::new(&format!("{}\n", text), filename, index + 1));
+    }
+    Ok(())
+}
+
+/// Assembles the contract source together with the library sources
+/// and returns the code cell with the debug map of the assembled code.
+pub fn compile_code_with_libs(source: &str, libs: &[&str]) -> Result<(Cell, DbgInfo)> {
+    let mut lines = Lines::new();
+    load_source_lines(source, &mut lines)?;
+    for lib in libs {
+        load_source_lines(lib, &mut lines)?;
+    }
+    let (code, debug_info) = compile_code_debuggable(lines)
+        .map_err(|e| format_err!("failed to compile {}: {}", source, e))?;
+    Ok((code.into_cell(), debug_info))
+}
+
+pub fn save_debug_info(debug_info: &DbgInfo, filename: &str) -> Status {
+    let file = File::create(filename)
+        .map_err(|e| format_err!("failed to create debug map file {}: {}", filename, e))?;
+    serde_json::to_writer_pretty(file, debug_info)
+        .map_err(|e| format_err!("failed to write debug map: {}", e))?;
+    Ok(())
+}
+
+pub fn decode_hex_string(hex_str: String) -> Result<(Vec<u8>, usize)> {
+    if hex_str.to_ascii_lowercase().starts_with('x') {
+        let buf = SliceData::from_string(&hex_str[1..])
+            .map_err(|_| format_err!("body {} is invalid literal slice", hex_str))?;
+        Ok((buf.get_bytestring(0), buf.remaining_bits()))
+    } else {
+        let buf = hex::decode(&hex_str)
+            .map_err(|_| format_err!("body {} is invalid hex string", hex_str))?;
+        let buf_bits = buf.len() * 8;
+        Ok((buf, buf_bits))
+    }
+}
+
+/// Computes the id of the get-method by its name.
+pub fn get_method_id(name: &str) -> u32 {
+    (XMODEM.checksum(name.as_bytes()) as u32) | 0x10000
+}
+
 pub fn get_now() -> u32 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -112,6 +224,11 @@ pub fn get_now() -> u32 {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_get_method_id() {
+        assert_eq!(get_method_id("seqno"), 85143);
+    }
+
     #[test]
     fn test_bouncable_address() {
         let addr = hex::decode("fcb91a3a3816d0f7b8c2c76108b8a9bc5a6b7a55bd79f8ab101c52db29232260")
@@ -119,4 +236,86 @@ mod tests {
         let addr = calc_userfriendly_address(-1, &addr, true, true);
         assert_eq!(addr, "kf/8uRo6OBbQ97jCx2EIuKm8Wmt6Vb15+KsQHFLbKSMiYIny");
     }
+
+    #[test]
+    fn test_parse_address() {
+        let hex_addr = "fcb91a3a3816d0f7b8c2c76108b8a9bc5a6b7a55bd79f8ab101c52db29232260";
+        let raw = format!("-1:{}", hex_addr);
+        let expected = MsgAddressInt::from_str(&raw).unwrap();
+        let (addr, bounce, testnet) =
+            parse_userfriendly_address("kf/8uRo6OBbQ97jCx2EIuKm8Wmt6Vb15+KsQHFLbKSMiYIny").unwrap();
+        assert_eq!(addr, expected);
+        assert!(bounce && testnet);
+        let url_safe = to_url_safe_address("kf/8uRo6OBbQ97jCx2EIuKm8Wmt6Vb15+KsQHFLbKSMiYIny");
+        assert_eq!(url_safe, "kf_8uRo6OBbQ97jCx2EIuKm8Wmt6Vb15-KsQHFLbKSMiYIny");
+        assert_eq!(parse_address(&url_safe).unwrap(), expected);
+        assert_eq!(parse_address(&raw).unwrap(), expected);
+
+        let (_, bounce, testnet) = parse_userfriendly_address(&calc_userfriendly_address(
+            -1,
+            &hex::decode(hex_addr).unwrap(),
+            false,
+            false,
+        ))
+        .unwrap();
+        assert!(!bounce && !testnet);
+        assert!(parse_address("kf/8uRo6OBbQ97jCx2EIuKm8Wmt6Vb15+KsQHFLbKSMiYInz").is_err());
+    }
+
+    #[test]
+    fn test_save_load_account() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let state_init = StateInit {
+            code: Some(
+                BuilderData::with_raw(vec![0x11], 8)
+                    .unwrap()
+                    .into_cell()
+                    .unwrap(),
+            ),
+            data: Some(Cell::default()),
+            ..Default::default()
+        };
+        let balance = CurrencyCollection::with_grams(12345);
+        let account = Account::active_by_init_code_hash(
+            addr.clone(),
+            balance.clone(),
+            100,
+            state_init,
+            false,
+        )
+        .unwrap();
+        let filename = std::env::temp_dir().join(format!(
+            "tvm_linker_test_account_{}.boc",
+            std::process::id()
+        ));
+        let filename = filename.to_str().unwrap();
+        save_account(&account, filename).unwrap();
+        let loaded = load_account(filename).unwrap();
+        assert_eq!(loaded, account);
+        assert_eq!(loaded.get_addr(), Some(&addr));
+        assert_eq!(loaded.balance(), Some(&balance));
+        std::fs::remove_file(filename).unwrap();
+        assert!(load_account(filename).is_err());
+    }
+
+    #[test]
+    fn test_compile_code_with_libs() {
+        let dir = std::env::temp_dir();
+        let source = dir.join(format!(
+            "tvm_linker_test_source_{}.code",
+            std::process::id()
+        ));
+        let lib = dir.join(format!("tvm_linker_test_lib_{}.code", std::process::id()));
+        std::fs::write(&source, "PUSHINT 1\nPUSHINT 2\n").unwrap();
+        std::fs::write(&lib, "ADD\n").unwrap();
+
+        let (code, debug_info) =
+            compile_code_with_libs(source.to_str().unwrap(), &[lib.to_str().unwrap()]).unwrap();
+        assert!(code.bit_length() > 0);
+        assert!(debug_info.get(&code.repr_hash()).is_some());
+        std::fs::remove_file(source).unwrap();
+        std::fs::remove_file(lib).unwrap();
+
+        assert!(compile_code_with_libs("not_existing_source.code", &[]).is_err());
+    }
 }
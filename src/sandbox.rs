@@ -0,0 +1,559 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::{collections::BTreeMap, str::FromStr};
+
+use anyhow::{bail, format_err};
+use ever_assembler::DbgInfo;
+use ever_block::{
+    BuilderData, Cell, CommonMsgInfo, CurrencyCollection, MsgAddressInt, OutAction, Result,
+    SliceData, StateInit, Status,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::abi::{build_abi_body, decode_call};
+use crate::keyman::load_signer;
+use crate::printer::execution_result_printer;
+use crate::program::{decode_hex_string, get_now, load_from_file, parse_address, save_to_file};
+use crate::scenario::value_to_arg;
+use crate::testcall::{
+    call_contract, currency_to_arg, decode_balance, load_config, load_debug_info, ExecutionResult,
+    MsgInfo, TestCallParams, TraceLevel,
+};
+
+const SEND_ALL_BALANCE: u8 = 128;
+const SEND_REMAINING_VALUE: u8 = 64;
+const SEND_IGNORE_ERRORS: u8 = 2;
+const BOUNCE_BODY_BITS: usize = 256;
+
+/// Description of the sandbox: accounts and initial messages.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SandboxConfig {
+    pub accounts: Vec<AccountConfig>,
+    pub messages: Vec<MessageConfig>,
+    pub config: Option<String>,
+    pub now: Option<u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    pub address: String,
+    pub tvc: String,
+    /// Account balance in the format of the `--balance` option
+    pub balance: Option<Value>,
+    pub abi: Option<String>,
+    pub debug_map: Option<String>,
+}
+
+/// Initial message: external if `value` is not set, internal otherwise.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageConfig {
+    pub dst: String,
+    pub src: Option<String>,
+    pub value: Option<Value>,
+    pub method: Option<String>,
+    pub params: Option<Value>,
+    pub header: Option<Value>,
+    /// Raw body (a bitstring like x09c_ or a hex string) used instead of ABI encoding
+    pub body: Option<String>,
+    pub sign: Option<String>,
+    #[serde(default = "default_bounce")]
+    pub bounce: bool,
+}
+
+fn default_bounce() -> bool {
+    true
+}
+
+struct SandboxAccount {
+    tvc: String,
+    state_init: StateInit,
+    balance: CurrencyCollection,
+    abi: Option<String>,
+    debug_info: Option<DbgInfo>,
+}
+
+struct QueuedMessage {
+    src: Option<MsgAddressInt>,
+    dst: MsgAddressInt,
+    value: Option<CurrencyCollection>,
+    body: Option<SliceData>,
+    key_file: Option<Option<String>>,
+    bounce: bool,
+    bounced: bool,
+    created_lt: u64,
+}
+
+pub struct Sandbox {
+    accounts: BTreeMap<String, SandboxAccount>,
+    queue: BTreeMap<(u64, u64), QueuedMessage>,
+    config: Option<Cell>,
+    now: u32,
+    lt: u64,
+    seq_no: u64,
+    trace_level: TraceLevel,
+}
+
+pub fn load_sandbox_config(filename: &str) -> Result<SandboxConfig> {
+    let text = std::fs::read_to_string(filename)
+        .map_err(|e| format_err!("failed to read sandbox file {}: {}", filename, e))?;
+    serde_json::from_str(&text).map_err(|e| format_err!("failed to parse sandbox: {}", e))
+}
+
+fn bounce_body(body: Option<&SliceData>) -> Result<SliceData> {
+    let mut builder = BuilderData::new();
+    builder.append_u32(0xffffffff)?;
+    if let Some(body) = body {
+        let bits = std::cmp::min(body.remaining_bits(), BOUNCE_BODY_BITS);
+        builder.append_raw(&body.get_bytestring(0), bits)?;
+    }
+    SliceData::load_builder(builder)
+}
+
+impl Sandbox {
+    pub fn new(config: &SandboxConfig, trace_level: TraceLevel) -> Result<Self> {
+        let mut accounts = BTreeMap::new();
+        for account in &config.accounts {
+            let address = parse_address(&account.address)?;
+            let balance = account.balance.as_ref().map(value_to_arg);
+            let (_, balance) = decode_balance(balance.as_deref())?;
+            accounts.insert(
+                address.to_string(),
+                SandboxAccount {
+                    tvc: account.tvc.clone(),
+                    state_init: load_from_file(&account.tvc)?,
+                    balance,
+                    abi: account.abi.clone(),
+                    debug_info: account.debug_map.as_deref().and_then(load_debug_info),
+                },
+            );
+        }
+        let mut sandbox = Self {
+            accounts,
+            queue: BTreeMap::new(),
+            config: config.config.as_deref().and_then(load_config),
+            now: config.now.unwrap_or_else(get_now),
+            lt: 1_000_000,
+            seq_no: 0,
+            trace_level,
+        };
+        for message in &config.messages {
+            sandbox.push_initial_message(message)?;
+        }
+        Ok(sandbox)
+    }
+
+    fn push_initial_message(&mut self, config: &MessageConfig) -> Status {
+        let dst = parse_address(&config.dst)?;
+        let internal = config.value.is_some();
+        let body = match (&config.body, &config.method) {
+            (Some(body), _) => {
+                let (buf, buf_bits) = decode_hex_string(body.to_string())?;
+                Some(SliceData::from_raw(buf, buf_bits))
+            }
+            (None, Some(method)) => {
+                let abi = self
+                    .accounts
+                    .get(&dst.to_string())
+                    .and_then(|account| account.abi.clone())
+                    .ok_or_else(|| {
+                        format_err!("ABI of {} must be supplied to call {}", dst, method)
+                    })?;
+                let params = config
+                    .params
+                    .as_ref()
+                    .map_or("{}".to_string(), |params| params.to_string());
+                let header = config.header.as_ref().map(|header| header.to_string());
+                let signer = config.sign.as_deref().map(load_signer).transpose()?;
+                let body = build_abi_body(
+                    &abi,
+                    method,
+                    &params,
+                    header.as_deref(),
+                    signer.as_deref(),
+                    internal,
+                    Some(dst.to_string()),
+                )?;
+                Some(SliceData::load_builder(body)?)
+            }
+            (None, None) => None,
+        };
+        let key_file = match (&config.body, internal) {
+            (Some(_), false) => Some(config.sign.clone()),
+            _ => None,
+        };
+        let value = match &config.value {
+            Some(value) => Some(decode_balance(Some(&value_to_arg(value)))?.1),
+            None => None,
+        };
+        let src = config.src.as_deref().map(parse_address).transpose()?;
+        self.enqueue(QueuedMessage {
+            src,
+            dst,
+            value,
+            body,
+            key_file,
+            bounce: config.bounce,
+            bounced: false,
+            created_lt: 0,
+        });
+        Ok(())
+    }
+
+    fn next_lt(&mut self) -> u64 {
+        self.lt += 1;
+        self.lt
+    }
+
+    fn enqueue(&mut self, mut message: QueuedMessage) {
+        if message.created_lt == 0 {
+            message.created_lt = self.next_lt();
+        }
+        self.seq_no += 1;
+        self.queue
+            .insert((message.created_lt, self.seq_no), message);
+    }
+
+    /// Delivers queued messages in the order of their logical time.
+    /// Returns the number of executed transactions.
+    pub fn run(&mut self, max_messages: usize) -> Result<usize> {
+        let mut count = 0;
+        while let Some(key) = self.queue.keys().next().cloned() {
+            if count == max_messages {
+                bail!("message limit {} is reached", max_messages)
+            }
+            let message = self.queue.remove(&key).unwrap();
+            count += 1;
+            self.deliver(count, message)?;
+        }
+        Ok(count)
+    }
+
+    fn deliver(&mut self, number: usize, message: QueuedMessage) -> Status {
+        let dst_key = message.dst.to_string();
+        println!(
+            "MESSAGE {} (lt {}): {} -> {}{}",
+            number,
+            message.created_lt,
+            message
+                .src
+                .as_ref()
+                .map_or("external".to_string(), |src| src.to_string()),
+            dst_key,
+            message
+                .value
+                .as_ref()
+                .map_or(String::new(), |value| format!(
+                    ", value {}",
+                    currency_to_arg(value)
+                )),
+        );
+        let mut account = match self.accounts.remove(&dst_key) {
+            Some(account) => account,
+            None => {
+                println!("Destination account {} does not exist", dst_key);
+                return self.bounce(&message);
+            }
+        };
+        self.print_call(&account, &message);
+
+        let balance_before = account.balance.clone();
+        if let Some(value) = &message.value {
+            account.balance.add(value)?;
+        }
+        let result = self.execute(&account, &message);
+        let result = match result {
+            Ok(result) => {
+                print!("{}", execution_result_printer(&result));
+                result
+            }
+            Err(err) => {
+                self.accounts.insert(dst_key, account);
+                return Err(err);
+            }
+        };
+
+        let trans_lt = self.next_lt();
+        let outbound = if result.is_vm_success {
+            self.process_actions(&mut account, &message, &result)
+        } else {
+            None
+        };
+        match outbound {
+            Some(outbound) => {
+                account.state_init = result.state_init;
+                self.accounts.insert(dst_key, account);
+                for mut out_msg in outbound {
+                    out_msg.created_lt = self.next_lt();
+                    self.enqueue(out_msg);
+                }
+            }
+            None => {
+                println!("Transaction {} is aborted", trans_lt);
+                account.balance = balance_before;
+                self.accounts.insert(dst_key, account);
+                self.bounce(&message)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn print_call(&self, account: &SandboxAccount, message: &QueuedMessage) {
+        let decoded = match (&account.abi, &message.body) {
+            (Some(abi), Some(body)) if !message.bounced => {
+                decode_call(abi, body.clone(), message.value.is_some()).ok()
+            }
+            _ => None,
+        };
+        if let Some((function, params)) = decoded {
+            println!("Call: {} {}", function, params);
+        }
+    }
+
+    fn execute(
+        &self,
+        account: &SandboxAccount,
+        message: &QueuedMessage,
+    ) -> Result<ExecutionResult> {
+        let value = message.value.as_ref().map(currency_to_arg);
+        let src = message.src.as_ref().map(|src| src.to_string());
+        let balance = currency_to_arg(&account.balance);
+        let mut params = TestCallParams::new(MsgInfo {
+            balance: value.as_deref(),
+            src: src.as_deref(),
+            now: self.now,
+            bounced: message.bounced,
+            body: message.body.clone(),
+            bounce: Some(message.bounce),
+            created_lt: Some(message.created_lt),
+            ..Default::default()
+        })
+        .with_balance(&balance)
+        .with_trace_level(self.trace_level)
+        .with_debug_info(account.debug_info.clone());
+        if let Some(config) = &self.config {
+            params = params.with_config(config.clone());
+        }
+        if let Some(key_file) = &message.key_file {
+            params = params.with_key_file(key_file.as_deref());
+        }
+        call_contract(message.dst.clone(), account.state_init.clone(), params)
+    }
+
+    /// Emulates sending of messages by the action phase.
+    /// Returns `None` if the action phase fails.
+    fn process_actions(
+        &self,
+        account: &mut SandboxAccount,
+        message: &QueuedMessage,
+        result: &ExecutionResult,
+    ) -> Option<Vec<QueuedMessage>> {
+        let mut outbound = Vec::new();
+        for action in result.out_actions.iter() {
+            let (mode, out_msg) = match action {
+                OutAction::SendMsg { mode, out_msg } => (*mode, out_msg),
+                _ => continue,
+            };
+            let header = match out_msg.header() {
+                CommonMsgInfo::IntMsgInfo(header) => header,
+                CommonMsgInfo::ExtOutMsgInfo(_) => {
+                    println!("External outbound message from {}", message.dst);
+                    continue;
+                }
+                CommonMsgInfo::ExtInMsgInfo(_) => continue,
+            };
+            let mut value = header.value.clone();
+            if mode & SEND_ALL_BALANCE != 0 {
+                value = account.balance.clone();
+            } else if mode & SEND_REMAINING_VALUE != 0 {
+                if let Some(inbound) = &message.value {
+                    value.add(inbound).ok()?;
+                }
+            }
+            if !account.balance.sub(&value).unwrap_or(false) {
+                println!(
+                    "Not enough balance to send {} to {}",
+                    currency_to_arg(&value),
+                    header.dst
+                );
+                if mode & SEND_IGNORE_ERRORS != 0 {
+                    continue;
+                }
+                return None;
+            }
+            outbound.push(QueuedMessage {
+                src: Some(message.dst.clone()),
+                dst: header.dst.clone(),
+                value: Some(value),
+                body: out_msg.body(),
+                key_file: None,
+                bounce: header.bounce,
+                bounced: false,
+                created_lt: 0,
+            });
+        }
+        Some(outbound)
+    }
+
+    fn bounce(&mut self, message: &QueuedMessage) -> Status {
+        let (src, value) = match (&message.src, &message.value) {
+            (Some(src), Some(value)) if message.bounce && !message.bounced => (src, value),
+            _ => return Ok(()),
+        };
+        println!("Message is bounced back to {}", src);
+        let bounced = QueuedMessage {
+            src: Some(message.dst.clone()),
+            dst: src.clone(),
+            value: Some(value.clone()),
+            body: Some(bounce_body(message.body.as_ref())?),
+            key_file: None,
+            bounce: false,
+            bounced: true,
+            created_lt: 0,
+        };
+        self.enqueue(bounced);
+        Ok(())
+    }
+
+    pub fn print_balances(&self) {
+        println!("Account balances:");
+        for (address, account) in &self.accounts {
+            println!("{}: {}", address, currency_to_arg(&account.balance));
+        }
+    }
+
+    /// Saves the states of all accounts to their tvc files.
+    pub fn save(self) -> Status {
+        for account in self.accounts.into_values() {
+            save_to_file(account.state_init, Some(&account.tvc), 0, false)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_currency_to_arg() {
+        let mut value = CurrencyCollection::with_grams(1000);
+        assert_eq!(currency_to_arg(&value), "1000");
+        value.set_other(1, 25).unwrap();
+        let arg = currency_to_arg(&value);
+        let (main, decoded) = decode_balance(Some(&arg)).unwrap();
+        assert_eq!(main, 1000);
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_bounce_body() {
+        let body = SliceData::from_raw(vec![0x11; 40], 320);
+        let bounced = bounce_body(Some(&body)).unwrap();
+        assert_eq!(bounced.remaining_bits(), 32 + BOUNCE_BODY_BITS);
+        assert_eq!(
+            bounced.get_bytestring(0)[..5],
+            [0xff, 0xff, 0xff, 0xff, 0x11]
+        );
+
+        let bounced = bounce_body(None).unwrap();
+        assert_eq!(bounced.remaining_bits(), 32);
+    }
+
+    fn save_contract(name: &str, code: Vec<u8>) -> String {
+        let tvc = std::env::temp_dir().join(format!(
+            "tvm_linker_test_sandbox_{}_{}.tvc",
+            name,
+            std::process::id()
+        ));
+        let tvc = tvc.to_str().unwrap().to_string();
+        let bits = code.len() * 8;
+        let state_init = StateInit {
+            code: Some(
+                BuilderData::with_raw(code, bits)
+                    .unwrap()
+                    .into_cell()
+                    .unwrap(),
+            ),
+            data: Some(Cell::default()),
+            ..Default::default()
+        };
+        save_to_file(state_init, Some(&tvc), 0, true).unwrap();
+        tvc
+    }
+
+    #[test]
+    fn test_deliver_and_bounce() {
+        // PUSHINT 1; DROP
+        let accepting = save_contract("accepting", vec![0x71, 0x30]);
+        // THROW 42
+        let failing = save_contract("failing", vec![0xF2, 0x2A]);
+        let first = format!("0:{}", "11".repeat(32));
+        let second = format!("0:{}", "22".repeat(32));
+        let config: SandboxConfig = serde_json::from_value(serde_json::json!({
+            "accounts": [
+                { "address": first, "tvc": accepting, "balance": 1000 },
+                { "address": second, "tvc": failing, "balance": 1000 },
+            ],
+            "messages": [
+                { "src": first, "dst": second, "value": 100 },
+                { "src": second, "dst": first, "value": 50, "bounce": false },
+            ],
+            "now": 100,
+        }))
+        .unwrap();
+        let mut sandbox = Sandbox::new(&config, TraceLevel::None).unwrap();
+        std::fs::remove_file(accepting).unwrap();
+        std::fs::remove_file(failing).unwrap();
+
+        // both messages and the bounce of the first one are delivered
+        assert_eq!(sandbox.run(10).unwrap(), 3);
+        let balance = |address: &str| sandbox.accounts[address].balance.clone();
+        // the value is credited to the first account and returned by the bounce
+        assert_eq!(balance(&first), CurrencyCollection::with_grams(1150));
+        // the failed transaction does not change the balance
+        assert_eq!(balance(&second), CurrencyCollection::with_grams(1000));
+        assert!(sandbox.queue.is_empty());
+    }
+
+    #[test]
+    fn test_message_order() {
+        let mut sandbox = Sandbox {
+            accounts: BTreeMap::new(),
+            queue: BTreeMap::new(),
+            config: None,
+            now: 0,
+            lt: 0,
+            seq_no: 0,
+            trace_level: TraceLevel::None,
+        };
+        let dst = MsgAddressInt::from_str(&"0".repeat(64)).unwrap();
+        for created_lt in [5, 3, 0] {
+            sandbox.enqueue(QueuedMessage {
+                src: None,
+                dst: dst.clone(),
+                value: None,
+                body: None,
+                key_file: None,
+                bounce: false,
+                bounced: false,
+                created_lt,
+            });
+        }
+        let order: Vec<u64> = sandbox.queue.values().map(|msg| msg.created_lt).collect();
+        assert_eq!(order, vec![1, 3, 5]);
+    }
+}
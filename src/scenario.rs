@@ -0,0 +1,556 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::str::FromStr;
+
+use anyhow::{bail, format_err};
+use ever_assembler::DbgInfo;
+use ever_block::{Cell, Message, MsgAddressInt, Result, SliceData, StateInit};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::abi::{build_abi_body, decode_body};
+use crate::coverage::Coverage;
+use crate::keyman::load_signer;
+use crate::printer::execution_result_printer;
+use crate::program::{decode_hex_string, get_now, load_from_file, parse_address};
+use crate::testcall::{
+    call_contract, load_config, load_debug_info, ExecutionResult, MsgInfo, TestCallParams,
+    TraceLevel,
+};
+
+/// Script of consecutive contract calls executed against one contract.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Contract tvc file
+    pub contract: String,
+    pub address: Option<String>,
+    pub abi: Option<String>,
+    pub config: Option<String>,
+    pub debug_map: Option<String>,
+    /// Account balance in the format of the `--balance` option
+    pub balance: Option<Value>,
+    pub now: Option<u32>,
+    pub steps: Vec<Step>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Step {
+    External {
+        name: Option<String>,
+        #[serde(flatten)]
+        call: Call,
+        sign: Option<String>,
+        expect: Option<Expectation>,
+    },
+    Internal {
+        name: Option<String>,
+        #[serde(flatten)]
+        call: Call,
+        value: Value,
+        src: Option<String>,
+        #[serde(default)]
+        bounced: bool,
+        expect: Option<Expectation>,
+    },
+    Ticktock {
+        name: Option<String>,
+        #[serde(default)]
+        tock: bool,
+        expect: Option<Expectation>,
+    },
+    SetTime {
+        now: u32,
+    },
+    AdvanceTime {
+        seconds: u32,
+    },
+    SetBalance {
+        balance: Value,
+    },
+}
+
+#[derive(Default, Deserialize)]
+pub struct Call {
+    pub method: Option<String>,
+    pub params: Option<Value>,
+    pub header: Option<Value>,
+    /// Raw body (a bitstring like x09c_ or a hex string) used instead of ABI encoding
+    pub body: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expectation {
+    pub exit_code: Option<i32>,
+    pub success: Option<bool>,
+    pub gas: Option<GasRange>,
+    /// Decoded output of the called method
+    pub output: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GasRange {
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+}
+
+pub fn load_scenario(filename: &str) -> Result<Scenario> {
+    let text = std::fs::read_to_string(filename)
+        .map_err(|e| format_err!("failed to read scenario file {}: {}", filename, e))?;
+    if filename.ends_with(".yaml") || filename.ends_with(".yml") {
+        serde_yaml::from_str(&text).map_err(|e| format_err!("failed to parse scenario: {}", e))
+    } else {
+        serde_json::from_str(&text).map_err(|e| format_err!("failed to parse scenario: {}", e))
+    }
+}
+
+/// Converts a JSON value to the format of the command line arguments.
+pub fn value_to_arg(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+pub struct ScenarioRunner {
+    scenario: Scenario,
+    address: MsgAddressInt,
+    state_init: StateInit,
+    balance: Option<String>,
+    now: u32,
+    config: Option<Cell>,
+    debug_info: Option<DbgInfo>,
+    trace_level: TraceLevel,
+    coverage: Option<Coverage>,
+}
+
+impl ScenarioRunner {
+    pub fn new(scenario: Scenario, trace_level: TraceLevel) -> Result<Self> {
+        let address = match &scenario.address {
+            Some(address) => parse_address(address)?,
+            None => MsgAddressInt::from_str(&"0".repeat(64))?,
+        };
+        let state_init = load_from_file(&scenario.contract)?;
+        let config = scenario.config.as_deref().and_then(load_config);
+        let debug_info = scenario.debug_map.as_deref().and_then(load_debug_info);
+        Ok(Self {
+            address,
+            state_init,
+            balance: scenario.balance.as_ref().map(value_to_arg),
+            now: scenario.now.unwrap_or_else(get_now),
+            config,
+            debug_info,
+            trace_level,
+            coverage: None,
+            scenario,
+        })
+    }
+
+    /// Accumulates executed source lines of all steps into the coverage.
+    pub fn with_coverage(mut self, coverage: Coverage) -> Self {
+        self.coverage = Some(coverage);
+        self
+    }
+
+    pub fn state_init(&self) -> &StateInit {
+        &self.state_init
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Runs all steps and returns the number of steps with failed expectations.
+    pub fn run(&mut self) -> Result<usize> {
+        let steps = std::mem::take(&mut self.scenario.steps);
+        let mut failed = 0;
+        for (index, step) in steps.iter().enumerate() {
+            let errors = self.run_step(index + 1, step)?;
+            if !errors.is_empty() {
+                failed += 1;
+                for error in errors {
+                    println!("STEP {} FAILED: {}", index + 1, error);
+                }
+            }
+        }
+        self.scenario.steps = steps;
+        Ok(failed)
+    }
+
+    fn run_step(&mut self, number: usize, step: &Step) -> Result<Vec<String>> {
+        match step {
+            Step::External {
+                name,
+                call,
+                sign,
+                expect,
+            } => {
+                print_step_header(number, "external", name);
+                let (body, key_file) = match &call.body {
+                    Some(body) => (Some(raw_body(body)?), Some(sign.as_deref())),
+                    None => (self.build_body(call, sign.as_deref(), false)?, None),
+                };
+                let msg_info = self.msg_info(None, None, false, body);
+                let result = self.call(msg_info, key_file, None)?;
+                Ok(self.check(&result, call, false, expect))
+            }
+            Step::Internal {
+                name,
+                call,
+                value,
+                src,
+                bounced,
+                expect,
+            } => {
+                print_step_header(number, "internal", name);
+                let body = match &call.body {
+                    Some(body) => Some(raw_body(body)?),
+                    None => self.build_body(call, None, true)?,
+                };
+                let value = value_to_arg(value);
+                let msg_info = self.msg_info(Some(&value), src.as_deref(), *bounced, body);
+                let result = self.call(msg_info, None, None)?;
+                Ok(self.check(&result, call, true, expect))
+            }
+            Step::Ticktock { name, tock, expect } => {
+                print_step_header(number, "ticktock", name);
+                let msg_info = self.msg_info(None, None, false, None);
+                let result = self.call(msg_info, None, Some(if *tock { -1 } else { 0 }))?;
+                Ok(self.check(&result, &Call::default(), false, expect))
+            }
+            Step::SetTime { now } => {
+                self.now = *now;
+                Ok(vec![])
+            }
+            Step::AdvanceTime { seconds } => {
+                self.now = advance_time(self.now, *seconds)?;
+                Ok(vec![])
+            }
+            Step::SetBalance { balance } => {
+                self.balance = Some(value_to_arg(balance));
+                Ok(vec![])
+            }
+        }
+    }
+
+    fn msg_info<'a>(
+        &self,
+        value: Option<&'a str>,
+        src: Option<&'a str>,
+        bounced: bool,
+        body: Option<SliceData>,
+    ) -> MsgInfo<'a> {
+        MsgInfo {
+            balance: value,
+            src,
+            now: self.now,
+            bounced,
+            body,
+            ..Default::default()
+        }
+    }
+
+    fn build_body(
+        &self,
+        call: &Call,
+        sign: Option<&str>,
+        internal: bool,
+    ) -> Result<Option<SliceData>> {
+        let method = match &call.method {
+            Some(method) => method,
+            None => return Ok(None),
+        };
+        let abi = self
+            .scenario
+            .abi
+            .as_deref()
+            .ok_or_else(|| format_err!("ABI must be supplied to call method {}", method))?;
+        let params = call
+            .params
+            .as_ref()
+            .map_or("{}".to_string(), |params| params.to_string());
+        let header = call.header.as_ref().map(|header| header.to_string());
+        let signer = sign.map(load_signer).transpose()?;
+        let body = build_abi_body(
+            abi,
+            method,
+            &params,
+            header.as_deref(),
+            signer.as_deref(),
+            internal,
+            Some(self.address.to_string()),
+        )?;
+        Ok(Some(SliceData::load_builder(body)?))
+    }
+
+    fn call(
+        &mut self,
+        msg_info: MsgInfo,
+        key_file: Option<Option<&str>>,
+        ticktock: Option<i8>,
+    ) -> Result<ExecutionResult> {
+        let mut params = TestCallParams::new(msg_info)
+            .with_trace_level(self.trace_level)
+            .with_debug_info(self.debug_info.clone());
+        if let Some(balance) = &self.balance {
+            params = params.with_balance(balance);
+        }
+        if let Some(config) = &self.config {
+            params = params.with_config(config.clone());
+        }
+        if let Some(key_file) = key_file {
+            params = params.with_key_file(key_file);
+        }
+        if let Some(ticktock) = ticktock {
+            params = params.with_ticktock(ticktock);
+        }
+        if self.coverage.is_some() {
+            params = params.with_trace_collection();
+        }
+        let result = call_contract(self.address.clone(), self.state_init.clone(), params)?;
+        print!("{}", execution_result_printer(&result));
+        if let Some(coverage) = &mut self.coverage {
+            coverage.add_trace(&result.trace);
+        }
+        if result.is_vm_success {
+            self.state_init = result.state_init.clone();
+        }
+        Ok(result)
+    }
+
+    fn check(
+        &self,
+        result: &ExecutionResult,
+        call: &Call,
+        internal: bool,
+        expect: &Option<Expectation>,
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+        let expect = match expect {
+            Some(expect) => expect,
+            None => return errors,
+        };
+        if let Some(exit_code) = expect.exit_code {
+            if result.exit_code != exit_code {
+                errors.push(format!(
+                    "exit code {} is not equal to expected {}",
+                    result.exit_code, exit_code
+                ));
+            }
+        }
+        if let Some(success) = expect.success {
+            if result.is_vm_success != success {
+                errors.push(format!(
+                    "compute phase success is {}, expected {}",
+                    result.is_vm_success, success
+                ));
+            }
+        }
+        if let Some(gas) = &expect.gas {
+            if gas.min.map_or(false, |min| result.gas_used < min)
+                || gas.max.map_or(false, |max| result.gas_used > max)
+            {
+                errors.push(format!(
+                    "gas used {} is out of expected range [{}, {}]",
+                    result.gas_used,
+                    gas.min.map_or("-".to_string(), |min| min.to_string()),
+                    gas.max.map_or("-".to_string(), |max| max.to_string()),
+                ));
+            }
+        }
+        if let Some(expected) = &expect.output {
+            match self.decode_output(&result.out_messages, call, internal) {
+                Some(output) if &output == expected => {}
+                Some(output) => errors.push(format!(
+                    "output {} is not equal to expected {}",
+                    output, expected
+                )),
+                None => errors.push("no decodable output message found".to_string()),
+            }
+        }
+        errors
+    }
+
+    fn decode_output(&self, messages: &[Message], call: &Call, internal: bool) -> Option<Value> {
+        let abi = self.scenario.abi.as_deref()?;
+        let method = call.method.as_deref()?;
+        messages.iter().find_map(|msg| {
+            let body = msg.body()?;
+            let output = decode_body(abi, method, body, internal).ok()?;
+            serde_json::from_str(&output).ok()
+        })
+    }
+}
+
+fn raw_body(body: &str) -> Result<SliceData> {
+    let (buf, buf_bits) = decode_hex_string(body.to_string())?;
+    Ok(SliceData::from_raw(buf, buf_bits))
+}
+
+fn print_step_header(number: usize, kind: &str, name: &Option<String>) {
+    match name {
+        Some(name) => println!("STEP {} ({}): {}", number, kind, name),
+        None => println!("STEP {} ({})", number, kind),
+    }
+}
+
+/// Runs the scenario file, the coverage of all steps is merged into the lcov file if specified.
+pub fn run_scenario(
+    filename: &str,
+    trace_level: TraceLevel,
+    coverage_file: Option<&str>,
+) -> Result<StateInit> {
+    let scenario = load_scenario(filename)?;
+    let mut runner = ScenarioRunner::new(scenario, trace_level)?;
+    if let Some(coverage_file) = coverage_file {
+        let code = runner.state_init.code.as_ref();
+        let coverage = Coverage::load(coverage_file, code, runner.debug_info.as_ref())?;
+        runner = runner.with_coverage(coverage);
+    }
+    let failed = runner.run()?;
+    if let (Some(coverage), Some(coverage_file)) = (runner.coverage(), coverage_file) {
+        coverage.save(coverage_file)?;
+        println!("{}", coverage.summary());
+    }
+    if failed != 0 {
+        bail!("{} scenario step(s) failed", failed)
+    }
+    Ok(runner.state_init().clone())
+}
+
+fn advance_time(now: u32, seconds: u32) -> Result<u32> {
+    now.checked_add(seconds).ok_or_else(|| {
+        format_err!(
+            "time overflow: cannot advance {} by {} seconds",
+            now,
+            seconds
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_steps() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "contract": "contract.tvc",
+                "abi": "contract.abi.json",
+                "steps": [
+                    { "action": "external", "method": "constructor", "expect": { "exit_code": 0 } },
+                    { "action": "advance_time", "seconds": 60 },
+                    { "action": "internal", "value": 1000000000, "method": "touch", "params": {},
+                      "expect": { "gas": { "max": 10000 }, "output": { "value0": "1" } } },
+                    { "action": "set_balance", "balance": { "main": 100, "extra": { "1": 10 } } },
+                    { "action": "ticktock", "tock": true }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(scenario.steps.len(), 5);
+        assert!(matches!(
+            scenario.steps[1],
+            Step::AdvanceTime { seconds: 60 }
+        ));
+        match &scenario.steps[2] {
+            Step::Internal { call, value, .. } => {
+                assert_eq!(call.method.as_deref(), Some("touch"));
+                assert_eq!(value_to_arg(value), "1000000000");
+            }
+            _ => panic!("internal step expected"),
+        }
+        match &scenario.steps[3] {
+            Step::SetBalance { balance } => {
+                assert_eq!(value_to_arg(balance), r#"{"extra":{"1":10},"main":100}"#)
+            }
+            _ => panic!("set_balance step expected"),
+        }
+    }
+
+    #[test]
+    fn test_advance_time() {
+        assert_eq!(advance_time(100, 60).unwrap(), 160);
+        assert!(advance_time(u32::MAX - 10, 60).is_err());
+    }
+
+    #[test]
+    fn test_run_steps() {
+        let tvc = std::env::temp_dir().join(format!(
+            "tvm_linker_test_scenario_{}.tvc",
+            std::process::id()
+        ));
+        let tvc = tvc.to_str().unwrap();
+        // PUSHINT 1; DROP
+        let code = ever_block::BuilderData::with_raw(vec![0x71, 0x30], 16).unwrap();
+        let state_init = StateInit {
+            code: Some(code.into_cell().unwrap()),
+            data: Some(Cell::default()),
+            ..Default::default()
+        };
+        crate::program::save_to_file(state_init, Some(tvc), 0, true).unwrap();
+        let scenario: Scenario = serde_json::from_value(serde_json::json!({
+            "contract": tvc,
+            "balance": 1000000,
+            "now": 100,
+            "steps": [
+                { "action": "external", "expect": {
+                    "exit_code": 0, "success": true, "gas": { "min": 1, "max": 10000 },
+                }},
+                { "action": "internal", "value": 1000, "expect": { "exit_code": 5 } },
+                { "action": "advance_time", "seconds": 60 },
+                { "action": "ticktock", "expect": { "gas": { "max": 1 } } },
+                { "action": "external", "expect": { "output": {} } },
+            ],
+        }))
+        .unwrap();
+        let mut runner = ScenarioRunner::new(scenario, TraceLevel::None).unwrap();
+        std::fs::remove_file(tvc).unwrap();
+
+        // the internal, ticktock and output expectations fail
+        assert_eq!(runner.run().unwrap(), 3);
+        assert_eq!(runner.now, 160);
+
+        let step: Step = serde_json::from_value(serde_json::json!({
+            "action": "internal", "value": 1000, "expect": { "exit_code": 5, "success": false },
+        }))
+        .unwrap();
+        assert_eq!(
+            runner.run_step(1, &step).unwrap(),
+            vec![
+                "exit code 0 is not equal to expected 5",
+                "compute phase success is true, expected false",
+            ]
+        );
+        let step: Step = serde_json::from_value(serde_json::json!({
+            "action": "external", "expect": { "gas": { "max": 1 }, "output": {} },
+        }))
+        .unwrap();
+        let errors = runner.run_step(2, &step).unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("gas used "));
+        assert_eq!(errors[1], "no decodable output message found");
+    }
+
+    #[test]
+    fn test_parse_unknown_action() {
+        let result = serde_json::from_str::<Scenario>(
+            r#"{ "contract": "c.tvc", "steps": [ { "action": "unknown" } ] }"#,
+        );
+        assert!(result.is_err());
+    }
+}
@@ -11,16 +11,23 @@
  * limitations under the License.
  */
 
-use std::{fs::File, str::FromStr, sync::Arc};
+use std::{
+    fs::File,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
 
-use anyhow::format_err;
-use ever_assembler::DbgInfo;
+use anyhow::{bail, format_err};
+use ever_abi::contract::{AbiVersion, ABI_VERSION_1_0, ABI_VERSION_2_3};
+use ever_assembler::{DbgInfo, DbgPos};
 use ever_block::{
-    ed25519_sign_with_secret, AccountId, BuilderData, Cell, HashmapE, Result, SliceData, Status,
+    read_single_root_boc, BuilderData, Cell, Ed25519PublicKey, HashmapE, Result, SliceData, Status,
+    UInt256,
 };
 use ever_block::{
-    CurrencyCollection, Deserializable, ExternalInboundMessageHeader, Grams, InternalMessageHeader,
-    Message, MsgAddressExt, MsgAddressInt, OutAction, OutActions, Serializable, StateInit,
+    Account, ConfigParamEnum, ConfigParams, CurrencyCollection, Deserializable,
+    ExternalInboundMessageHeader, GasLimitsPrices, Grams, InternalMessageHeader, Message,
+    MsgAddressExt, MsgAddressInt, OutAction, OutActions, Serializable, StateInit, MASTERCHAIN_ID,
 };
 use ever_vm::{
     error::tvm_exception,
@@ -29,15 +36,14 @@ use ever_vm::{
     stack::{integer::IntegerData, savelist::SaveList, Stack, StackItem},
     SmartContractInfo,
 };
-use log::{log_enabled, Level::Error};
 use serde_json::Value;
 use simplelog::{Config, LevelFilter, SimpleLogger};
 
-use crate::keyman::Keypair;
-use crate::printer::msg_printer;
-use crate::program::{get_now, load_from_file};
+use crate::keyman::load_signer;
+use crate::program::{get_now, load_from_file, parse_address};
 
 const DEFAULT_ACCOUNT_BALANCE: &str = "100000000000";
+const DEFAULT_CAPABILITIES: u64 = 0x880116ae; // Default capabilities on the main network
 
 fn create_external_inbound_msg(
     src: MsgAddressExt,
@@ -60,41 +66,160 @@ fn create_internal_msg(
     src_addr: MsgAddressInt,
     dst_addr: MsgAddressInt,
     value: CurrencyCollection,
-    lt: u64,
-    at: u32,
-    body: Option<SliceData>,
-    bounced: bool,
+    msg_info: &MsgInfo,
 ) -> Message {
     let mut hdr = InternalMessageHeader::with_addresses(src_addr, dst_addr, value);
-    hdr.bounce = !bounced;
-    hdr.bounced = bounced;
+    hdr.bounce = msg_info.bounce.unwrap_or(!msg_info.bounced);
+    hdr.bounced = msg_info.bounced;
     hdr.ihr_disabled = true;
     hdr.ihr_fee = Grams::from(0u64);
-    hdr.created_lt = lt;
-    hdr.created_at = at.into();
+    hdr.fwd_fee = Grams::from(msg_info.fwd_fee);
+    hdr.created_lt = msg_info.created_lt.unwrap_or(1);
+    hdr.created_at = msg_info.now.into();
     let mut msg = Message::with_int_header(hdr);
-    if let Some(body) = body {
+    if let Some(body) = msg_info.body.clone() {
         msg.set_body(body);
     }
     msg
 }
 
-fn sign_body(body: &mut SliceData, key_file: Option<&str>) -> Status {
-    let mut signed_body = body.as_builder();
-    let mut sign_builder = BuilderData::new();
-    if let Some(f) = key_file {
-        let pair = Keypair::from_file(f)?;
-        let pub_key = pair.public.to_bytes();
-        let signature =
-            ed25519_sign_with_secret(pair.private.as_bytes(), body.cell().repr_hash().as_slice())?;
-        sign_builder.append_raw(&signature, signature.len() * 8)?;
-        sign_builder.append_raw(&pub_key, pub_key.len() * 8)?;
-    }
-    signed_body.checked_prepend_reference(sign_builder.into_cell()?)?;
+/// Signs the raw body of the external message: ABI 1 puts the signature and the public key
+/// into the first reference, ABI 2 puts the signature bit and the signature at the beginning
+/// of the body. The signature is checked after signing.
+pub fn sign_body(
+    body: &mut SliceData,
+    key_file: Option<&str>,
+    abi_version: AbiVersion,
+    address: &MsgAddressInt,
+) -> Status {
+    let signer = key_file.map(load_signer).transpose()?;
+    let signature = match &signer {
+        Some(signer) => Some(signer.sign(body_sign_hash(body, abi_version, address)?.as_slice())?),
+        None => None,
+    };
+    let signed_body = if abi_version.major == 1 {
+        let mut sign_builder = BuilderData::new();
+        if let (Some(signer), Some(signature)) = (&signer, signature) {
+            let pub_key = signer.public_key()?;
+            sign_builder.append_raw(&signature, signature.len() * 8)?;
+            sign_builder.append_raw(&pub_key, pub_key.len() * 8)?;
+        }
+        let mut signed_body = body.as_builder();
+        signed_body.checked_prepend_reference(sign_builder.into_cell()?)?;
+        signed_body
+    } else {
+        let mut signed_body = BuilderData::new();
+        match signature {
+            Some(signature) => {
+                signed_body.append_bit_one()?;
+                signed_body.append_raw(&signature, signature.len() * 8)?;
+            }
+            None => {
+                signed_body.append_bit_zero()?;
+            }
+        }
+        signed_body.append_builder(&body.as_builder())?;
+        signed_body
+    };
     *body = SliceData::load_cell(signed_body.into_cell()?)?;
+    if let Some(signer) = signer {
+        if !verify_body_signature(body, abi_version, &signer.public_key()?, address)? {
+            bail!("signature of the body does not match the public key");
+        }
+    }
     Ok(())
 }
 
+/// Hash of the unsigned body, since ABI 2.3 the destination address is prepended to the body.
+fn body_sign_hash(
+    unsigned: &SliceData,
+    abi_version: AbiVersion,
+    address: &MsgAddressInt,
+) -> Result<UInt256> {
+    let mut builder = match abi_version >= ABI_VERSION_2_3 {
+        true => address.write_to_new_cell()?,
+        false => BuilderData::new(),
+    };
+    builder.append_builder(&unsigned.as_builder())?;
+    Ok(builder.into_cell()?.repr_hash())
+}
+
+/// Checks the signature of the body signed for the ABI version.
+pub fn verify_body_signature(
+    body: &SliceData,
+    abi_version: AbiVersion,
+    public_key: &[u8; 32],
+    address: &MsgAddressInt,
+) -> Result<bool> {
+    let mut unsigned = body.clone();
+    let signature = if abi_version.major == 1 {
+        SliceData::load_cell(unsigned.checked_drain_reference()?)?.get_next_bytes(64)?
+    } else {
+        if !unsigned.get_next_bit()? {
+            bail!("body is not signed");
+        }
+        unsigned.get_next_bytes(64)?
+    };
+    let signature: [u8; 64] = signature
+        .try_into()
+        .map_err(|v: Vec<u8>| format_err!("invalid signature length {}", v.len()))?;
+    let hash = body_sign_hash(&unsigned, abi_version, address)?;
+    Ok(Ed25519PublicKey::from_bytes(public_key)?.verify(hash.as_slice(), &signature))
+}
+
+/// Block and transaction parameters placed into the c7 register
+#[derive(Clone, Default)]
+pub struct BlockInfo {
+    pub block_lt: u64,
+    pub trans_lt: u64,
+    /// Random seed of the block, the account seed is derived from it and the account address
+    pub rand_seed: UInt256,
+    /// Hash of the code the account was deployed with, the current code hash if not specified
+    pub init_code_hash: Option<UInt256>,
+    pub storage_fee: u128,
+    pub due_payment: Option<u128>,
+    /// Tuple of the last masterchain blocks and the previous key block
+    pub prev_blocks: Option<StackItem>,
+}
+
+fn block_id_item(block: &Value) -> Result<StackItem> {
+    let number = |name: &str| -> Result<StackItem> {
+        let value = &block[name];
+        let number = match value {
+            Value::Number(number) => IntegerData::from_str_radix(&number.to_string(), 10)?,
+            Value::String(hex) => IntegerData::from_str_radix(hex.trim_start_matches("0x"), 16)?,
+            _ => bail!("block id field {} must be a number or a hex string", name),
+        };
+        Ok(StackItem::integer(number))
+    };
+    Ok(StackItem::tuple(vec![
+        number("workchain")?,
+        number("shard")?,
+        number("seq_no")?,
+        number("root_hash")?,
+        number("file_hash")?,
+    ]))
+}
+
+/// Builds the previous blocks tuple from the json object with `last_mc_blocks` array
+/// and `prev_key_block` of block ids (`workchain`, `shard`, `seq_no`, `root_hash`, `file_hash`).
+pub fn prev_blocks_info(value: &Value) -> Result<StackItem> {
+    let last_mc_blocks = value["last_mc_blocks"]
+        .as_array()
+        .map(|blocks| blocks.iter().map(block_id_item).collect::<Result<Vec<_>>>())
+        .transpose()?
+        .unwrap_or_default();
+    let prev_key_block = match &value["prev_key_block"] {
+        Value::Null => StackItem::None,
+        block => block_id_item(block)?,
+    };
+    Ok(StackItem::tuple(vec![
+        StackItem::tuple(last_mc_blocks),
+        prev_key_block,
+    ]))
+}
+
+#[allow(clippy::too_many_arguments)]
 fn initialize_registers(
     data: SliceData,
     mycode: Cell,
@@ -103,24 +228,41 @@ fn initialize_registers(
     balance: CurrencyCollection,
     config_params: Option<Cell>,
     capabilities: u64,
+    block_info: &BlockInfo,
 ) -> Result<SaveList> {
     let mut ctrls = SaveList::new();
-    let info = SmartContractInfo {
+    let init_code_hash = block_info
+        .init_code_hash
+        .clone()
+        .unwrap_or_else(|| mycode.repr_hash());
+    let mut info = SmartContractInfo {
         capabilities,
         balance,
         myself: SliceData::load_cell(myself.serialize()?).unwrap(),
         mycode,
         unix_time,
         config_params,
+        block_lt: block_info.block_lt,
+        trans_lt: block_info.trans_lt,
+        storage_fee_collected: block_info.storage_fee,
+        due_payment: block_info.due_payment,
         ..Default::default()
     };
-    // TODO info.set_init_code_hash()
+    info.set_init_code_hash(init_code_hash);
+    // the account seed is derived from the block seed the same way the executor does
+    info.calc_rand_seed(
+        block_info.rand_seed.clone(),
+        &myself.address().get_bytestring(0),
+    );
+    if let Some(prev_blocks) = &block_info.prev_blocks {
+        info.prev_blocks_info = prev_blocks.clone();
+    }
     ctrls.put(4, &mut StackItem::Cell(data.into_cell()))?;
     ctrls.put(7, &mut info.into_temp_data_item())?;
     Ok(ctrls)
 }
 
-fn init_logger(debug: bool) -> Status {
+pub fn init_logger(debug: bool) -> Status {
     SimpleLogger::init(
         if debug {
             LevelFilter::Trace
@@ -139,27 +281,34 @@ fn init_logger(debug: bool) -> Status {
     Ok(())
 }
 
-fn create_inbound_msg(
+/// Returns the selector of the main contract function for the inbound message
+/// or ticktock event: 0 for internal, -1 for external and -2 for ticktock.
+pub fn get_selector(msg_info: &MsgInfo, ticktock: Option<i8>) -> i32 {
+    match &msg_info.message {
+        Some(msg) if msg.is_internal() => 0,
+        Some(_) => -1,
+        None if msg_info.balance.is_some() => 0,
+        None if ticktock.is_some() => -2,
+        None => -1,
+    }
+}
+
+pub fn create_inbound_msg(
     selector: i32,
     msg_info: &MsgInfo,
-    dst: AccountId,
+    dst: &MsgAddressInt,
 ) -> Result<Option<Message>> {
+    if let Some(msg) = &msg_info.message {
+        return Ok(Some(msg.clone()));
+    }
     let (_, value) = decode_balance(msg_info.balance)?;
-    Ok(match selector {
+    let mut msg = match selector {
         0 => {
             let src = match msg_info.src {
-                Some(s) => MsgAddressInt::from_str(s)?,
+                Some(s) => parse_address(s)?,
                 None => MsgAddressInt::with_standart(None, 0, [0u8; 32].into())?,
             };
-            Some(create_internal_msg(
-                src,
-                MsgAddressInt::with_standart(None, 0, dst)?,
-                value,
-                1,
-                get_now(),
-                msg_info.body.clone(),
-                msg_info.bounced,
-            ))
+            create_internal_msg(src, dst.clone(), value, msg_info)
         }
         -1 => {
             let src = match msg_info.src {
@@ -167,47 +316,36 @@ fn create_inbound_msg(
                 None => MsgAddressExt::with_extern(SliceData::from_raw(vec![0x55; 8], 64))
                     .map_err(|e| format_err!("Failed to create address: {}", e))?,
             };
-            Some(create_external_inbound_msg(
-                src,
-                MsgAddressInt::with_standart(None, 0, dst)
-                    .map_err(|e| format_err!("Failed to convert address: {}", e))?,
-                msg_info.body.clone(),
-            ))
+            create_external_inbound_msg(src, dst.clone(), msg_info.body.clone())
         }
-        _ => None,
-    })
+        _ => return Ok(None),
+    };
+    if let Some(state_init) = &msg_info.state_init {
+        msg.set_state_init(state_init.clone());
+    }
+    Ok(Some(msg))
 }
 
-fn decode_actions<F>(actions: StackItem, state: &mut StateInit, action_decoder: F) -> Status
-where
-    F: Fn(SliceData, bool),
-{
-    if let StackItem::Cell(cell) = &actions {
-        let actions: OutActions = OutActions::construct_from(&mut SliceData::load_cell_ref(cell)?)?;
-        println!("Output actions:\n----------------");
-        for act in actions {
-            match act {
-                OutAction::SendMsg { mode: _, out_msg } => {
-                    println!("Action(SendMsg):\n{}", msg_printer(&out_msg)?);
-                    if let Some(b) = out_msg.body() {
-                        action_decoder(b, out_msg.is_internal());
-                    }
-                }
-                OutAction::SetCode { new_code: code } => {
-                    println!("Action(SetCode)");
-                    state.code = Some(code);
-                }
-                OutAction::ReserveCurrency { .. } => {
-                    println!("Action(ReserveCurrency)");
-                }
-                OutAction::ChangeLibrary { .. } => {
-                    println!("Action(ChangeLibrary)");
-                }
-                _ => println!("Action(Unknown)"),
-            };
-        }
+/// Returns the value of the inbound internal message in nanotokens.
+fn get_msg_value(selector: i32, msg_info: &MsgInfo) -> Result<u64> {
+    Ok(get_msg_currency(selector, msg_info)?.grams.as_u128() as u64)
+}
+
+fn get_msg_currency(selector: i32, msg_info: &MsgInfo) -> Result<CurrencyCollection> {
+    if selector != 0 {
+        return Ok(CurrencyCollection::default());
+    }
+    match &msg_info.message {
+        Some(msg) => Ok(msg.get_value().cloned().unwrap_or_default()),
+        None => Ok(decode_balance(msg_info.balance)?.1),
+    }
+}
+
+fn load_out_actions(actions: &StackItem) -> Result<OutActions> {
+    match actions {
+        StackItem::Cell(cell) => OutActions::construct_from(&mut SliceData::load_cell_ref(cell)?),
+        _ => Ok(OutActions::default()),
     }
-    Ok(())
 }
 
 pub fn load_code_and_data(state_init: &StateInit) -> (SliceData, SliceData) {
@@ -217,7 +355,7 @@ pub fn load_code_and_data(state_init: &StateInit) -> (SliceData, SliceData) {
     (code, data)
 }
 
-fn decode_balance(value: Option<&str>) -> Result<(u64, CurrencyCollection)> {
+pub fn decode_balance(value: Option<&str>) -> Result<(u64, CurrencyCollection)> {
     let value = value.unwrap_or(DEFAULT_ACCOUNT_BALANCE);
     if let Ok(main) = value.parse::<u64>() {
         Ok((main, CurrencyCollection::with_grams(main)))
@@ -252,12 +390,57 @@ fn decode_balance(value: Option<&str>) -> Result<(u64, CurrencyCollection)> {
     }
 }
 
+/// Converts currencies to the format accepted by the `--balance` option.
+pub fn currency_to_arg(value: &CurrencyCollection) -> String {
+    if value.other.is_empty() {
+        return value.grams.to_string();
+    }
+    let mut extra = serde_json::Map::new();
+    value
+        .other
+        .iterate_with_keys(|key: u32, amount| {
+            extra.insert(key.to_string(), Value::from_str(&amount.to_string())?);
+            Ok(true)
+        })
+        .ok();
+    serde_json::json!({
+        "main": Value::from_str(&value.grams.to_string()).unwrap_or_default(),
+        "extra": extra,
+    })
+    .to_string()
+}
+
 pub struct MsgInfo<'a> {
     pub balance: Option<&'a str>,
     pub src: Option<&'a str>,
     pub now: u32,
     pub bounced: bool,
     pub body: Option<SliceData>,
+    /// Bounce flag of the internal message, `!bounced` if not specified
+    pub bounce: Option<bool>,
+    pub fwd_fee: u64,
+    pub created_lt: Option<u64>,
+    /// StateInit attached to the message
+    pub state_init: Option<StateInit>,
+    /// Complete message used as is instead of the fields above
+    pub message: Option<Message>,
+}
+
+impl Default for MsgInfo<'_> {
+    fn default() -> Self {
+        Self {
+            balance: None,
+            src: None,
+            now: get_now(),
+            bounced: false,
+            body: None,
+            bounce: None,
+            fwd_fee: 0,
+            created_lt: None,
+            state_init: None,
+            message: None,
+        }
+    }
 }
 
 pub fn load_debug_info(filename: &str) -> Option<DbgInfo> {
@@ -274,30 +457,118 @@ pub fn load_config(filename: &str) -> Option<Cell> {
     data.into_cell().reference(0).ok()
 }
 
-#[derive(PartialEq)]
+/// Loads config parameters dictionary from a config contract TVC, a serialized
+/// `ConfigParams` structure or a bare dictionary boc.
+pub fn load_config_boc(filename: &str) -> Result<Cell> {
+    let root = read_single_root_boc(
+        std::fs::read(filename)
+            .map_err(|e| format_err!("failed to read config file {}: {}", filename, e))?,
+    )?;
+    if root.bit_length() == 256 && root.references_count() == 1 {
+        return root.reference(0);
+    }
+    // a fork of the bare dictionary also has two references, so the config contract
+    // state is accepted only if it has code and data with a valid dictionary
+    if root.references_count() >= 2 {
+        if let Ok(StateInit {
+            code: Some(_),
+            data: Some(data),
+            ..
+        }) = StateInit::construct_from_cell(root.clone())
+        {
+            if let Ok(params) = data.reference(0) {
+                if is_config_dictionary(&params) {
+                    return Ok(params);
+                }
+            }
+        }
+    }
+    if !is_config_dictionary(&root) {
+        bail!("{} is not a config parameters file", filename);
+    }
+    Ok(root)
+}
+
+fn is_config_dictionary(root: &Cell) -> bool {
+    HashmapE::with_hashmap(32, Some(root.clone())).len().is_ok()
+}
+
+/// Takes the address, state and balance for the compute phase of the transaction
+/// reproduced from the account and inbound message exported from the network.
+/// The balance is taken after the storage phase collected `storage_fee`, the message
+/// value is credited by `call_contract` in the real gas mode.
+pub fn replay_state(
+    account: &Account,
+    message: &Message,
+    storage_fee: u128,
+) -> Result<(MsgAddressInt, StateInit, String)> {
+    let addr = message
+        .dst_ref()
+        .cloned()
+        .ok_or_else(|| format_err!("message has no destination address"))?;
+    if let Some(account_addr) = account.get_addr() {
+        if *account_addr != addr {
+            bail!(
+                "message destination {} differs from the account address {}",
+                addr,
+                account_addr
+            );
+        }
+    }
+    let state_init = match account.state_init() {
+        Some(state_init) => state_init.clone(),
+        None => message
+            .state_init()
+            .cloned()
+            .ok_or_else(|| format_err!("account is not active and the message has no StateInit"))?,
+    };
+    let mut balance = account.balance().cloned().unwrap_or_default();
+    // the storage phase takes no more than the balance, the rest becomes due payment
+    let collected = storage_fee.min(balance.grams.as_u128());
+    balance.sub(&CurrencyCollection::with_grams(collected as u64))?;
+    Ok((addr, state_init, currency_to_arg(&balance)))
+}
+
+pub fn load_config_params(config_cell: &Cell) -> ConfigParams {
+    ConfigParams::with_address_and_root(
+        UInt256::from_str(&"5".repeat(64)).unwrap(), // -1:5555...
+        config_cell.clone(),
+    )
+}
+
+pub fn get_capabilities(config: &Option<Cell>) -> u64 {
+    match config {
+        Some(config_cell) => load_config_params(config_cell).capabilities(),
+        None => DEFAULT_CAPABILITIES,
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum TraceLevel {
     Full,
     Minimal,
     None,
 }
 
+pub fn find_position(info: &EngineTraceInfo, debug_info: &Option<DbgInfo>) -> Option<DbgPos> {
+    let cell_hash = info.cmd_code.cell().repr_hash();
+    let offset = info.cmd_code.pos();
+    debug_info
+        .as_ref()?
+        .get(&cell_hash)
+        .and_then(|offset_map| offset_map.get(&offset))
+        .cloned()
+}
+
 fn get_position(info: &EngineTraceInfo, debug_info: &Option<DbgInfo>) -> Option<String> {
-    if let Some(debug_info) = debug_info {
-        let cell_hash = info.cmd_code.cell().repr_hash();
-        let offset = info.cmd_code.pos();
-        let position = match debug_info.get(&cell_hash) {
-            Some(offset_map) => match offset_map.get(&offset) {
-                Some(pos) => format!("{}:{}", pos.filename, pos.line),
-                None => String::from("-:0 (offset not found)"),
-            },
-            None => String::from("-:0 (cell hash not found)"),
-        };
-        return Some(position);
-    }
-    None
+    find_position(info, debug_info).map(|pos| format!("{}:{}", pos.filename, pos.line))
 }
 
-fn trace_callback_minimal(_engine: &Engine, info: &EngineTraceInfo, debug_info: &Option<DbgInfo>) {
+pub fn trace_callback_minimal(
+    _engine: &Engine,
+    info: &EngineTraceInfo,
+    debug_info: &Option<DbgInfo>,
+) {
     print!(
         "{} {} {} {}",
         info.step, info.gas_used, info.gas_cmd, info.cmd_str
@@ -309,7 +580,7 @@ fn trace_callback_minimal(_engine: &Engine, info: &EngineTraceInfo, debug_info:
     println!();
 }
 
-fn trace_callback(
+pub fn trace_callback(
     _engine: &Engine,
     info: &EngineTraceInfo,
     extended: bool,
@@ -339,58 +610,206 @@ fn trace_callback(
     println!("----------------------------------------\n");
 }
 
-pub struct TestCallParams<'a, F: Fn(SliceData, bool)> {
+/// Callback invoked by the engine for each executed instruction
+pub type TraceHook = Arc<dyn Fn(&Engine, &EngineTraceInfo) + Send + Sync>;
+
+pub struct TestCallParams<'a> {
     pub balance: Option<&'a str>,
     pub msg_info: MsgInfo<'a>,
     pub config: Option<Cell>,
     pub key_file: Option<Option<&'a str>>,
+    /// ABI version defining the layout of the signed body
+    pub abi_version: AbiVersion,
     pub ticktock: Option<i8>,
     pub gas_limit: Option<i64>,
-    pub action_decoder: Option<F>,
+    /// Calculates gas from the config parameters and the message value
+    pub real_gas: bool,
+    pub block_info: BlockInfo,
     pub trace_level: TraceLevel,
+    pub collect_trace: bool,
+    /// Copies the stack into each collected trace event
+    pub collect_trace_stack: bool,
+    pub trace_hook: Option<TraceHook>,
     pub debug_info: Option<DbgInfo>,
     pub capabilities: u64,
 }
 
-pub fn call_contract<F>(
-    addr: MsgAddressInt,
-    state_init: StateInit,
-    params: TestCallParams<F>,
-) -> Result<(i32, StateInit, bool)>
-where
-    F: Fn(SliceData, bool),
-{
-    let func_selector = match params.msg_info.balance {
-        Some(_) => 0,
-        None => {
-            if params.ticktock.is_some() {
-                -2
-            } else {
-                -1
-            }
+impl Default for TestCallParams<'_> {
+    fn default() -> Self {
+        Self {
+            balance: None,
+            msg_info: MsgInfo::default(),
+            config: None,
+            key_file: None,
+            abi_version: ABI_VERSION_1_0,
+            ticktock: None,
+            gas_limit: None,
+            real_gas: false,
+            block_info: BlockInfo::default(),
+            trace_level: TraceLevel::None,
+            collect_trace: false,
+            collect_trace_stack: false,
+            trace_hook: None,
+            debug_info: None,
+            capabilities: DEFAULT_CAPABILITIES,
         }
-    };
+    }
+}
 
-    let msg = create_inbound_msg(func_selector, &params.msg_info, addr.address())?;
+impl<'a> TestCallParams<'a> {
+    pub fn new(msg_info: MsgInfo<'a>) -> Self {
+        Self {
+            msg_info,
+            ..Default::default()
+        }
+    }
 
-    if !log_enabled!(Error) {
-        init_logger(params.trace_level == TraceLevel::Full)?;
+    pub fn with_balance(mut self, balance: &'a str) -> Self {
+        self.balance = Some(balance);
+        self
     }
 
-    let mut state_init = state_init;
-    let (code, data) = load_code_and_data(&state_init);
+    /// Sets config parameters and takes the engine capabilities from them.
+    pub fn with_config(mut self, config: Cell) -> Self {
+        self.config = Some(config);
+        self.capabilities = get_capabilities(&self.config);
+        self
+    }
 
-    let (smc_value, smc_balance) = decode_balance(params.balance)?;
-    let registers = initialize_registers(
-        data,
-        code.clone().into_cell(),
-        addr.clone(),
-        params.msg_info.now,
-        smc_balance,
-        params.config,
-        params.capabilities,
-    )?;
+    /// Signs the body of the external message with the key from the file,
+    /// `None` leaves the signature empty.
+    pub fn with_key_file(mut self, key_file: Option<&'a str>) -> Self {
+        self.key_file = Some(key_file);
+        self
+    }
+
+    pub fn with_abi_version(mut self, abi_version: AbiVersion) -> Self {
+        self.abi_version = abi_version;
+        self
+    }
+
+    pub fn with_ticktock(mut self, ticktock: i8) -> Self {
+        self.ticktock = Some(ticktock);
+        self
+    }
+
+    pub fn with_gas_limit(mut self, gas_limit: i64) -> Self {
+        self.gas_limit = Some(gas_limit);
+        self
+    }
+
+    /// Calculates gas like the validator does, requires config parameters.
+    pub fn with_real_gas(mut self) -> Self {
+        self.real_gas = true;
+        self
+    }
 
+    pub fn with_block_info(mut self, block_info: BlockInfo) -> Self {
+        self.block_info = block_info;
+        self
+    }
+
+    pub fn with_trace_level(mut self, trace_level: TraceLevel) -> Self {
+        self.trace_level = trace_level;
+        self
+    }
+
+    /// Collects executed instructions into `ExecutionResult::trace`.
+    pub fn with_trace_collection(mut self) -> Self {
+        self.collect_trace = true;
+        self
+    }
+
+    /// Collects executed instructions together with stack snapshots.
+    pub fn with_trace_stack(mut self) -> Self {
+        self.collect_trace = true;
+        self.collect_trace_stack = true;
+        self
+    }
+
+    /// Calls the hook before each executed instruction, e.g. to stop in a debugger.
+    pub fn with_trace_hook(mut self, hook: TraceHook) -> Self {
+        self.trace_hook = Some(hook);
+        self
+    }
+
+    pub fn with_debug_info(mut self, debug_info: Option<DbgInfo>) -> Self {
+        self.debug_info = debug_info;
+        self
+    }
+}
+
+/// Executed instruction
+#[derive(Clone)]
+pub struct TraceEvent {
+    pub step: u32,
+    pub cmd_str: String,
+    pub gas_used: i64,
+    pub gas_cmd: i64,
+    pub cell_hash: UInt256,
+    pub offset: usize,
+    pub position: Option<DbgPos>,
+    /// Stack before the instruction, empty if stack snapshots are not collected
+    pub stack: Vec<StackItem>,
+}
+
+impl TraceEvent {
+    fn from_info(info: &EngineTraceInfo, debug_info: &Option<DbgInfo>, with_stack: bool) -> Self {
+        Self {
+            step: info.step,
+            cmd_str: info.cmd_str.clone(),
+            gas_used: info.gas_used,
+            gas_cmd: info.gas_cmd,
+            cell_hash: info.cmd_code.cell().repr_hash(),
+            offset: info.cmd_code.pos(),
+            position: find_position(info, debug_info),
+            stack: match with_stack {
+                true => info.stack.iter().cloned().collect(),
+                false => Vec::new(),
+            },
+        }
+    }
+}
+
+pub struct ExecutionResult {
+    pub exit_code: i32,
+    pub is_vm_success: bool,
+    pub gas_used: i64,
+    pub gas_limit: i64,
+    /// Unhandled exception terminated the execution
+    pub exception: Option<String>,
+    /// Source position of the last executed instruction (if debug map is supplied)
+    pub last_position: Option<String>,
+    pub stack: Vec<StackItem>,
+    /// Dump of the control registers after the execution
+    pub ctrls_dump: String,
+    /// Committed persistent data
+    pub c4: Option<Cell>,
+    /// Contract state with the committed data and the code replaced by SetCode action
+    pub state_init: StateInit,
+    pub out_actions: OutActions,
+    pub out_messages: Vec<Message>,
+    /// Executed instructions if trace collection is enabled
+    pub trace: Vec<TraceEvent>,
+}
+
+/// Executes the contract code with the inbound message or ticktock event
+/// described by the params. The contract state in the result is updated
+/// with the committed data and the new code if the compute phase is successful.
+pub fn call_contract(
+    addr: MsgAddressInt,
+    state_init: StateInit,
+    params: TestCallParams,
+) -> Result<ExecutionResult> {
+    let func_selector = get_selector(&params.msg_info, params.ticktock);
+    let msg = create_inbound_msg(func_selector, &params.msg_info, &addr)?;
+
+    let (mut smc_value, mut smc_balance) = decode_balance(params.balance)?;
+    if params.real_gas {
+        // the compute phase sees the balance after the credit phase of the internal message
+        smc_balance.add(&get_msg_currency(func_selector, &params.msg_info)?)?;
+        smc_value = smc_balance.grams.as_u128() as u64;
+    }
     let mut stack = Stack::new();
     if func_selector > -2 {
         let msg_cell = StackItem::Cell(
@@ -398,19 +817,18 @@ where
                 .serialize()?,
         );
 
-        let mut body = params.msg_info.body.unwrap_or_default();
+        let mut body = match &params.msg_info.message {
+            Some(msg) => msg.body().unwrap_or_default(),
+            None => params.msg_info.body.clone().unwrap_or_default(),
+        };
 
-        if func_selector == -1 {
+        if func_selector == -1 && params.msg_info.message.is_none() {
             if let Some(key_file) = params.key_file {
-                sign_body(&mut body, key_file)?;
+                sign_body(&mut body, key_file, params.abi_version, &addr)?;
             }
         }
 
-        let msg_value = if func_selector == 0 {
-            decode_balance(params.msg_info.balance)?.0 // for internal message
-        } else {
-            0 // for external message
-        };
+        let msg_value = get_msg_value(func_selector, &params.msg_info)?;
 
         stack
             .push(int!(smc_value)) // contract balance
@@ -428,17 +846,157 @@ where
             .push(int!(func_selector));
     }
 
-    let gas = if let Some(gas_limit) = params.gas_limit {
-        let mut tmp_gas = Gas::test();
-        tmp_gas.new_gas_limit(gas_limit);
-        tmp_gas
+    let gas = if params.real_gas {
+        let config = params
+            .config
+            .as_ref()
+            .ok_or_else(|| format_err!("config parameters are required to calculate gas"))?;
+        let prices = load_gas_prices(config, addr.workchain_id() == MASTERCHAIN_ID)?;
+        let is_special = load_config_params(config).is_special_account(&addr)?;
+        let msg_value = get_msg_value(func_selector, &params.msg_info)?;
+        realistic_gas(
+            &prices,
+            smc_value as u128,
+            msg_value as u128,
+            func_selector == -1,
+            is_special,
+            func_selector != -2,
+            params.gas_limit,
+        )
+    } else {
+        test_gas(params.gas_limit)
+    };
+    execute_code(addr, state_init, smc_balance, stack, gas, params)
+}
+
+/// Runs the get-method with the given id (see `get_method_id`), the arguments
+/// are pushed to the stack in order followed by the method id.
+pub fn run_get_method(
+    addr: MsgAddressInt,
+    state_init: StateInit,
+    method_id: u32,
+    args: Vec<StackItem>,
+    params: TestCallParams,
+) -> Result<ExecutionResult> {
+    let mut stack = Stack::new();
+    for arg in args {
+        stack.push(arg);
+    }
+    stack.push(int!(method_id));
+    let gas = test_gas(params.gas_limit);
+    let (_, balance) = decode_balance(params.balance)?;
+    execute_code(addr, state_init, balance, stack, gas, params)
+}
+
+/// Parses a get-method argument: an integer (decimal or 0x-prefixed hex),
+/// `null` or a base64 encoded BOC pushed as a cell.
+pub fn parse_stack_item(arg: &str) -> Result<StackItem> {
+    if arg == "null" {
+        return Ok(StackItem::None);
+    }
+    let (sign, abs) = match arg.strip_prefix('-') {
+        Some(abs) => ("-", abs),
+        None => ("", arg),
+    };
+    let number = match abs.strip_prefix("0x") {
+        Some(hex) => IntegerData::from_str_radix(&format!("{}{}", sign, hex), 16),
+        None => IntegerData::from_str_radix(arg, 10),
+    };
+    if let Ok(number) = number {
+        return Ok(StackItem::Integer(Arc::new(number)));
+    }
+    let bytes = base64::decode(arg).map_err(|_| {
+        format_err!(
+            "invalid stack argument {}: integer or base64 BOC expected",
+            arg
+        )
+    })?;
+    Ok(StackItem::Cell(read_single_root_boc(bytes)?))
+}
+
+fn test_gas(gas_limit: Option<i64>) -> Gas {
+    let mut gas = Gas::test();
+    if let Some(gas_limit) = gas_limit {
+        gas.new_gas_limit(gas_limit);
+    }
+    gas
+}
+
+pub fn load_gas_prices(config: &Cell, is_masterchain: bool) -> Result<GasLimitsPrices> {
+    let index = if is_masterchain { 20 } else { 21 };
+    match load_config_params(config).config(index)? {
+        Some(ConfigParamEnum::ConfigParam20(prices))
+        | Some(ConfigParamEnum::ConfigParam21(prices)) => Ok(prices),
+        _ => bail!("config parameter {} is not found", index),
+    }
+}
+
+/// Calculates gas limit, credit and maximum the way the validator does:
+/// the maximum is bought with the account balance (after the credit phase),
+/// the limit with the message value, external messages get a credit,
+/// special accounts use the special gas limit and non-ordinary (ticktock)
+/// transactions get the whole maximum.
+/// The limit and the maximum are additionally capped by `max_limit`.
+pub fn realistic_gas(
+    prices: &GasLimitsPrices,
+    acc_balance: u128,
+    msg_value: u128,
+    is_external: bool,
+    is_special: bool,
+    is_ordinary: bool,
+    max_limit: Option<i64>,
+) -> Gas {
+    let cap = |gas: u64| match max_limit {
+        Some(limit) => gas.min(limit.max(0) as u64),
+        None => gas,
+    };
+    let gas_max = if is_special {
+        prices.special_gas_limit
     } else {
-        Gas::test()
+        prices.gas_limit.min(prices.calc_gas(acc_balance))
     };
+    let gas_max = cap(gas_max);
+    let mut gas_credit = 0;
+    let gas_limit = if !is_ordinary {
+        gas_max
+    } else {
+        if is_external {
+            gas_credit = prices.gas_credit.min(gas_max);
+        }
+        gas_max.min(prices.calc_gas(msg_value))
+    };
+    Gas::new(
+        gas_limit as i64,
+        gas_credit as i64,
+        gas_max as i64,
+        prices.get_real_gas_price() as i64,
+    )
+}
+
+fn execute_code(
+    addr: MsgAddressInt,
+    state_init: StateInit,
+    smc_balance: CurrencyCollection,
+    stack: Stack,
+    gas: Gas,
+    params: TestCallParams,
+) -> Result<ExecutionResult> {
+    let mut state_init = state_init;
+    let (code, data) = load_code_and_data(&state_init);
+
+    let registers = initialize_registers(
+        data,
+        code.clone().into_cell(),
+        addr.clone(),
+        params.msg_info.now,
+        smc_balance,
+        params.config,
+        params.capabilities,
+        &params.block_info,
+    )?;
 
     let library_map = HashmapE::with_hashmap(256, state_init.library.root().cloned());
 
-    println!("Engine capabilities: {}", params.capabilities);
     let mut engine = Engine::with_capabilities(params.capabilities).setup_with_libraries(
         code,
         Some(registers),
@@ -448,48 +1006,96 @@ where
     );
     engine.set_trace(0);
     let debug_info = params.debug_info;
-    match params.trace_level {
-        TraceLevel::Full => engine.set_trace_callback(move |engine, info| {
-            trace_callback(engine, info, true, &debug_info);
-        }),
-        TraceLevel::Minimal => engine.set_trace_callback(move |engine, info| {
-            trace_callback_minimal(engine, info, &debug_info);
-        }),
-        TraceLevel::None => {}
+    let trace_level = params.trace_level;
+    let collect_trace = params.collect_trace;
+    let collect_trace_stack = params.collect_trace_stack;
+    let trace_hook = params.trace_hook;
+    let last_position = Arc::new(Mutex::new(None));
+    let trace = Arc::new(Mutex::new(Vec::new()));
+    if trace_level != TraceLevel::None
+        || collect_trace
+        || trace_hook.is_some()
+        || debug_info.is_some()
+    {
+        let last_position = last_position.clone();
+        let trace = trace.clone();
+        engine.set_trace_callback(move |engine, info| {
+            if info.info_type != EngineTraceInfoType::Dump {
+                if let Some(hook) = &trace_hook {
+                    hook(engine, info);
+                }
+                *last_position.lock().unwrap() = get_position(info, &debug_info);
+                if collect_trace {
+                    let event = TraceEvent::from_info(info, &debug_info, collect_trace_stack);
+                    trace.lock().unwrap().push(event);
+                }
+            }
+            match trace_level {
+                TraceLevel::Full => trace_callback(engine, info, true, &debug_info),
+                TraceLevel::Minimal => trace_callback_minimal(engine, info, &debug_info),
+                TraceLevel::None => {}
+            }
+        });
     }
+    let mut exception = None;
     let exit_code = engine.execute().unwrap_or_else(|exc| match tvm_exception(exc) {
         Ok(exc) => {
-            println!("Unhandled exception: {}", exc);
+            exception = Some(exc.to_string());
             exc.exception_or_custom_code()
         }
         _ => -1,
     });
 
     let is_vm_success = engine.get_committed_state().is_committed();
-    println!("TVM terminated with exit code {}", exit_code);
-    println!("Computing phase is success: {}", is_vm_success);
-    println!("Gas used: {}", engine.get_gas().get_gas_used());
-    println!();
-    println!("{}", engine.dump_stack("Post-execution stack state", false));
-    println!("{}", engine.dump_ctrls(false));
+    let gas_used = engine.get_gas().get_gas_used();
+    let gas_limit = engine.get_gas().get_gas_limit();
+    let last_position = last_position.lock().unwrap().take();
+    let stack = engine.stack().iter().cloned().collect();
+    let ctrls_dump = engine.dump_ctrls(false);
 
+    let mut out_actions = OutActions::default();
+    let mut out_messages = Vec::new();
+    let mut c4 = None;
     if is_vm_success {
-        if let Some(decoder) = params.action_decoder {
-            decode_actions(engine.get_actions(), &mut state_init, decoder)?;
+        let actions = engine.get_actions();
+        out_actions = load_out_actions(&actions)?;
+        for action in out_actions.iter() {
+            match action {
+                OutAction::SendMsg { out_msg, .. } => out_messages.push(out_msg.clone()),
+                OutAction::SetCode { new_code } => state_init.code = Some(new_code.clone()),
+                _ => {}
+            }
         }
 
-        state_init.data = match engine.get_committed_state().get_root() {
+        c4 = match engine.get_committed_state().get_root() {
             StackItem::Cell(root_cell) => Some(root_cell.clone()),
-            _ => panic!("cannot get root data: c4 register is not a cell."),
+            _ => bail!("cannot get root data: c4 register is not a cell."),
         };
+        state_init.data = c4.clone();
     }
+    let trace = std::mem::take(&mut *trace.lock().unwrap());
 
-    Ok((exit_code, state_init, is_vm_success))
+    Ok(ExecutionResult {
+        exit_code,
+        is_vm_success,
+        gas_used,
+        gas_limit,
+        exception,
+        last_position,
+        stack,
+        ctrls_dump,
+        c4,
+        state_init,
+        out_actions,
+        out_messages,
+        trace,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::printer::msg_printer;
 
     fn create_inbound_body(a: i32, b: i32, func_id: i32) -> Result<Cell> {
         let mut builder = BuilderData::new();
@@ -513,10 +1119,10 @@ mod tests {
             MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap(),
             MsgAddressInt::with_standart(None, 0, [0x22; 32].into()).unwrap(),
             CurrencyCollection::with_grams(12345678),
-            1,
-            2,
-            None,
-            false,
+            &MsgInfo {
+                now: 2,
+                ..Default::default()
+            },
         );
 
         println!(
@@ -529,6 +1135,438 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_inbound_msg_headers() {
+        let dst = MsgAddressInt::with_standart(None, -1, [0x22; 32].into()).unwrap();
+        let msg_info = MsgInfo {
+            balance: Some("1000"),
+            now: 5,
+            bounce: Some(false),
+            fwd_fee: 333,
+            created_lt: Some(77),
+            ..Default::default()
+        };
+        assert_eq!(get_selector(&msg_info, None), 0);
+        let msg = create_inbound_msg(0, &msg_info, &dst).unwrap().unwrap();
+        let hdr = msg.int_header().unwrap();
+        assert_eq!(hdr.dst, dst);
+        assert!(!hdr.bounce);
+        assert_eq!(hdr.fwd_fee, Grams::from(333u64));
+        assert_eq!(hdr.created_lt, 77);
+        assert_eq!(hdr.created_at, 5.into());
+        assert_eq!(get_msg_value(0, &msg_info).unwrap(), 1000);
+
+        let external = MsgInfo {
+            message: Some(create_external_inbound_msg(
+                MsgAddressExt::default(),
+                dst.clone(),
+                None,
+            )),
+            ..msg_info
+        };
+        assert_eq!(get_selector(&external, Some(0)), -1);
+        assert_eq!(
+            create_inbound_msg(-1, &external, &dst).unwrap(),
+            external.message
+        );
+        assert_eq!(get_msg_value(-1, &external).unwrap(), 0);
+    }
+
+    fn config_dictionary() -> Cell {
+        let mut dict = HashmapE::with_bit_len(32);
+        for index in [0u32, 1, 20, 34] {
+            let key = SliceData::load_builder(index.write_to_new_cell().unwrap()).unwrap();
+            let value = BuilderData::with_raw(vec![index as u8], 8)
+                .unwrap()
+                .into_cell()
+                .unwrap();
+            dict.setref(key, &value).unwrap();
+        }
+        dict.data().cloned().unwrap()
+    }
+
+    #[test]
+    fn test_load_config_boc() {
+        let dict = config_dictionary();
+        // the root of the bare dictionary is a fork with two references
+        assert_eq!(dict.references_count(), 2);
+        let filename = std::env::temp_dir().join("tvm_linker_config_params.boc");
+        let filename = filename.to_str().unwrap();
+
+        let mut params = BuilderData::with_raw(vec![0x55; 32], 256).unwrap();
+        params.checked_append_reference(dict.clone()).unwrap();
+        let params = params.into_cell().unwrap();
+        std::fs::write(filename, ever_block::write_boc(&params).unwrap()).unwrap();
+        assert_eq!(load_config_boc(filename).unwrap(), dict);
+
+        std::fs::write(filename, ever_block::write_boc(&dict).unwrap()).unwrap();
+        assert_eq!(load_config_boc(filename).unwrap(), dict);
+
+        let mut data = BuilderData::new();
+        data.checked_append_reference(dict.clone()).unwrap();
+        let state_init = StateInit {
+            code: Some(Cell::default()),
+            data: Some(data.into_cell().unwrap()),
+            ..Default::default()
+        };
+        let root = state_init.serialize().unwrap();
+        std::fs::write(filename, ever_block::write_boc(&root).unwrap()).unwrap();
+        assert_eq!(load_config_boc(filename).unwrap(), dict);
+
+        let invalid = BuilderData::with_raw(vec![0xAB], 8)
+            .unwrap()
+            .into_cell()
+            .unwrap();
+        std::fs::write(filename, ever_block::write_boc(&invalid).unwrap()).unwrap();
+        assert!(load_config_boc(filename).is_err());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    /// Contract replacing its code with the returned cell
+    fn set_code_contract() -> (StateInit, Cell) {
+        let new_code = BuilderData::with_raw(vec![0x77], 8)
+            .unwrap()
+            .into_cell()
+            .unwrap();
+        // PUSHREF new_code; SETCODE
+        let mut code = BuilderData::with_raw(vec![0x88, 0xFB, 0x04], 24).unwrap();
+        code.checked_append_reference(new_code.clone()).unwrap();
+        let state_init = StateInit {
+            code: Some(code.into_cell().unwrap()),
+            data: Some(Cell::default()),
+            ..Default::default()
+        };
+        (state_init, new_code)
+    }
+
+    fn call_set_code_contract() -> ExecutionResult {
+        let (state_init, _) = set_code_contract();
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let params = TestCallParams::new(MsgInfo {
+            balance: Some("1000"),
+            now: 1,
+            ..Default::default()
+        })
+        .with_balance("1000000");
+        call_contract(addr, state_init, params).unwrap()
+    }
+
+    #[test]
+    fn test_set_code_applied() {
+        let (state_init, new_code) = set_code_contract();
+        let result = call_set_code_contract();
+        assert!(result.is_vm_success);
+        assert_ne!(state_init.code, Some(new_code.clone()));
+        assert_eq!(result.state_init.code, Some(new_code));
+    }
+
+    #[test]
+    fn test_call_contract_does_not_print() {
+        // stdout of the test is captured by the harness, so the call is made in a child process
+        const CHILD_ENV: &str = "TVM_LINKER_TEST_SILENT_CHILD";
+        if std::env::var(CHILD_ENV).is_ok() {
+            println!("<call>");
+            call_set_code_contract();
+            println!("</call>");
+            return;
+        }
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "testcall::tests::test_call_contract_does_not_print",
+                "--nocapture",
+                "--test-threads=1",
+            ])
+            .env(CHILD_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("<call>\n</call>\n"), "{}", stdout);
+    }
+
+    #[test]
+    fn test_replay_state() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let state_init = StateInit {
+            code: Some(Cell::default()),
+            data: Some(Cell::default()),
+            ..Default::default()
+        };
+        let account = Account::active_by_init_code_hash(
+            addr.clone(),
+            CurrencyCollection::with_grams(2_000_000_000),
+            0,
+            state_init.clone(),
+            false,
+        )
+        .unwrap();
+        let src = MsgAddressInt::with_standart(None, 0, [0x22; 32].into()).unwrap();
+        let value = CurrencyCollection::with_grams(1_000_000_000);
+        let msg_info = MsgInfo {
+            now: 100,
+            ..Default::default()
+        };
+        let message = create_internal_msg(src.clone(), addr.clone(), value, &msg_info);
+
+        let (replay_addr, replay_state_init, balance) =
+            replay_state(&account, &message, 0).unwrap();
+        assert_eq!(replay_addr, addr);
+        assert_eq!(replay_state_init, state_init);
+        // the value is credited by call_contract
+        assert_eq!(balance, "2000000000");
+
+        let params = TestCallParams::new(MsgInfo {
+            now: msg_info.now,
+            message: Some(message),
+            ..Default::default()
+        })
+        .with_balance(&balance)
+        .with_config(gas_config())
+        .with_real_gas();
+        let result = call_contract(replay_addr, replay_state_init, params).unwrap();
+        assert!(result.is_vm_success);
+        // the limit is bought with the message value
+        assert_eq!(result.gas_limit, 1_000_000);
+
+        let other = create_internal_msg(addr, src, CurrencyCollection::default(), &msg_info);
+        assert!(replay_state(&account, &other, 0).is_err());
+    }
+
+    #[test]
+    fn test_replay_small_balance() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let account = Account::active_by_init_code_hash(
+            addr.clone(),
+            CurrencyCollection::with_grams(1_500_000),
+            0,
+            accept_contract(),
+            false,
+        )
+        .unwrap();
+        let src = MsgAddressInt::with_standart(None, 0, [0x22; 32].into()).unwrap();
+        let msg_info = MsgInfo {
+            now: 100,
+            ..Default::default()
+        };
+        let value = CurrencyCollection::with_grams(2_000_000);
+        let message = create_internal_msg(src, addr, value, &msg_info);
+
+        // the storage fee is taken before the compute phase
+        let (addr, state_init, balance) = replay_state(&account, &message, 500_000).unwrap();
+        assert_eq!(balance, "1000000");
+        let params = TestCallParams::new(MsgInfo {
+            now: msg_info.now,
+            message: Some(message.clone()),
+            ..Default::default()
+        })
+        .with_balance(&balance)
+        .with_config(gas_config())
+        .with_real_gas();
+        let result = call_contract(addr, state_init, params).unwrap();
+        assert!(result.is_vm_success);
+        // the maximum is bought with the balance and the value credited once
+        assert_eq!(result.gas_limit, 3000);
+
+        let (_, _, balance) = replay_state(&account, &message, 2_000_000).unwrap();
+        assert_eq!(balance, "0");
+    }
+
+    #[test]
+    fn test_sign_body_abi_versions() {
+        let pair = crate::keyman::Keypair::from_seed("sign body").unwrap();
+        let public = pair.public.to_bytes();
+        let filename = std::env::temp_dir().join("tvm_linker_test_sign_body.json");
+        let filename = filename.to_str().unwrap();
+        pair.save(filename, true).unwrap();
+        let address = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let other = MsgAddressInt::with_standart(None, 0, [0x22; 32].into()).unwrap();
+        let unsigned = SliceData::from_raw(vec![0x12, 0x34, 0x56, 0x78], 32);
+        for version in ["1.0", "2.0", "2.3"] {
+            let abi_version = AbiVersion::parse(version).unwrap();
+            let mut body = unsigned.clone();
+            sign_body(&mut body, Some(filename), abi_version, &address).unwrap();
+            assert!(verify_body_signature(&body, abi_version, &public, &address).unwrap());
+            assert_eq!(
+                verify_body_signature(&body, abi_version, &public, &other).unwrap(),
+                abi_version < ABI_VERSION_2_3
+            );
+        }
+
+        let mut body = unsigned.clone();
+        sign_body(&mut body, None, AbiVersion::parse("2.2").unwrap(), &address).unwrap();
+        assert!(!body.get_next_bit().unwrap());
+        assert_eq!(body, unsigned);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_parse_stack_item() {
+        assert_eq!(parse_stack_item("123").unwrap(), int!(123));
+        assert_eq!(parse_stack_item("-0x10").unwrap(), int!(-16));
+        assert_eq!(parse_stack_item("null").unwrap(), StackItem::None);
+        let boc = base64::encode(ever_block::write_boc(&Cell::default()).unwrap());
+        assert_eq!(
+            parse_stack_item(&boc).unwrap(),
+            StackItem::Cell(Cell::default())
+        );
+        assert!(parse_stack_item("abc!").is_err());
+    }
+
+    /// Config with the basechain gas prices: 1000 nanotokens for a gas unit.
+    fn gas_config() -> Cell {
+        let mut config = ConfigParams::default();
+        config
+            .set_config(ConfigParamEnum::ConfigParam21(GasLimitsPrices {
+                gas_price: 65536000,
+                gas_limit: 1_000_000,
+                special_gas_limit: 1_000_000,
+                gas_credit: 10_000,
+                ..Default::default()
+            }))
+            .unwrap();
+        config.config_params.data().cloned().unwrap()
+    }
+
+    /// ACCEPT; BALANCE: the gas limit is raised to the maximum and the c7 balance is pushed.
+    fn accept_contract() -> StateInit {
+        let code = BuilderData::with_raw(vec![0xF8, 0x00, 0xF8, 0x27], 32).unwrap();
+        StateInit {
+            code: Some(code.into_cell().unwrap()),
+            data: Some(Cell::default()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_real_gas_credited_balance() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        let params = TestCallParams::new(MsgInfo {
+            balance: Some("2000000"),
+            now: 1,
+            ..Default::default()
+        })
+        .with_balance("1000000")
+        .with_config(gas_config())
+        .with_real_gas();
+        let result = call_contract(addr, accept_contract(), params).unwrap();
+        assert!(result.is_vm_success);
+        // the maximum is bought with the credited balance
+        assert_eq!(result.gas_limit, 3000);
+        // the stack and c7 balances are credited as well
+        assert_eq!(result.stack[0], int!(3_000_000));
+        let c7_balance = result.stack.last().unwrap().as_tuple().unwrap();
+        assert_eq!(c7_balance[0], int!(3_000_000));
+    }
+
+    #[test]
+    fn test_realistic_gas() {
+        let prices = GasLimitsPrices {
+            gas_price: 65536000,
+            gas_limit: 1_000_000,
+            special_gas_limit: 100_000_000,
+            gas_credit: 10_000,
+            ..Default::default()
+        };
+        // ticktock of a special account
+        let gas = realistic_gas(&prices, 0, 0, false, true, false, None);
+        assert_eq!(gas.get_gas_limit(), 100_000_000);
+        let gas = realistic_gas(&prices, 0, 0, false, true, false, Some(5000));
+        assert_eq!(gas.get_gas_limit(), 5000);
+
+        // internal message: the maximum is bought with balance plus value,
+        // the limit with the value (1000 gas for 1_000_000 nanotokens)
+        let balance = 2_000_000 + 1_000_000;
+        let gas = realistic_gas(&prices, balance, 1_000_000, false, false, true, None);
+        assert_eq!(gas.get_gas_limit(), 1000);
+        assert_eq!(gas.get_gas_max(), 3000);
+        assert_eq!(gas.get_gas_credit(), 0);
+        let gas = realistic_gas(&prices, 3_000_000, 5_000_000, false, false, true, None);
+        assert_eq!(gas.get_gas_limit(), 3000);
+        let gas = realistic_gas(&prices, 3_000_000, 1_000_000, false, false, true, Some(700));
+        assert_eq!(gas.get_gas_limit(), 700);
+
+        // external message gets the credit limited by the maximum
+        let gas = realistic_gas(&prices, 1_000_000_000, 0, true, false, true, None);
+        assert_eq!(gas.get_gas_limit(), 0);
+        assert_eq!(gas.get_gas_credit(), 10_000);
+        let gas = realistic_gas(&prices, 5_000_000, 0, true, false, true, None);
+        assert_eq!(gas.get_gas_credit(), 5000);
+    }
+
+    #[test]
+    fn test_prev_blocks_info() {
+        let block = serde_json::json!({
+            "workchain": -1,
+            "shard": "8000000000000000",
+            "seq_no": 10,
+            "root_hash": "11".repeat(32),
+            "file_hash": "22".repeat(32),
+        });
+        let value =
+            serde_json::json!({ "last_mc_blocks": [block.clone()], "prev_key_block": block });
+        let item = prev_blocks_info(&value).unwrap();
+        let tuple = item.as_tuple().unwrap();
+        assert_eq!(tuple.len(), 2);
+        assert_eq!(tuple[0].as_tuple().unwrap().len(), 1);
+        assert_eq!(tuple[1].as_tuple().unwrap()[2], int!(10));
+        assert!(prev_blocks_info(&serde_json::json!({ "prev_key_block": {} })).is_err());
+    }
+
+    /// Runs RANDSEED for the account and returns the seed from the stack.
+    fn account_rand_seed(account: u8, block_seed: UInt256) -> StackItem {
+        let code = BuilderData::with_raw(vec![0xF8, 0x26], 16).unwrap();
+        let state_init = StateInit {
+            code: Some(code.into_cell().unwrap()),
+            data: Some(Cell::default()),
+            ..Default::default()
+        };
+        let addr = MsgAddressInt::with_standart(None, 0, [account; 32].into()).unwrap();
+        let params = TestCallParams::new(MsgInfo {
+            balance: Some("1000"),
+            now: 1,
+            ..Default::default()
+        })
+        .with_block_info(BlockInfo {
+            rand_seed: block_seed,
+            ..Default::default()
+        });
+        let result = call_contract(addr, state_init, params).unwrap();
+        assert!(result.is_vm_success);
+        result.stack.last().unwrap().clone()
+    }
+
+    #[test]
+    fn test_account_rand_seed() {
+        // zero block seed gives zero account seed as in the executor
+        assert_eq!(account_rand_seed(0x11, UInt256::default()), int!(0));
+        let block_seed = UInt256::from([0x33; 32]);
+        let seed = account_rand_seed(0x11, block_seed.clone());
+        assert_ne!(seed, int!(0));
+        assert_eq!(seed, account_rand_seed(0x11, block_seed.clone()));
+        assert_ne!(seed, account_rand_seed(0x22, block_seed));
+    }
+
+    #[test]
+    fn test_params_builder() {
+        let params = TestCallParams::new(MsgInfo {
+            balance: Some("1000"),
+            ..Default::default()
+        })
+        .with_balance("5000")
+        .with_gas_limit(100000)
+        .with_trace_collection();
+        assert_eq!(params.balance, Some("5000"));
+        assert_eq!(params.msg_info.balance, Some("1000"));
+        assert_eq!(params.gas_limit, Some(100000));
+        assert_eq!(params.capabilities, DEFAULT_CAPABILITIES);
+        assert!(params.collect_trace);
+        assert!(!params.collect_trace_stack);
+        let params = params.with_trace_stack();
+        assert!(params.collect_trace && params.collect_trace_stack);
+        assert!(params.key_file.is_none());
+    }
+
     #[test]
     fn test_decode_balance() {
         let (main, balance) =
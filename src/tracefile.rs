@@ -0,0 +1,203 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+use anyhow::format_err;
+use ever_block::{Result, Status};
+use serde::{Deserialize, Serialize};
+
+use crate::testcall::TraceEvent;
+
+/// One executed instruction in the trace file, stored as a json line.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TraceRecord {
+    pub step: u32,
+    pub cmd: String,
+    pub gas_used: i64,
+    pub gas_cmd: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
+    /// Stack from the top, saved only if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stack: Option<Vec<String>>,
+}
+
+impl TraceRecord {
+    pub fn from_event(event: &TraceEvent, with_stack: bool) -> Self {
+        Self {
+            step: event.step,
+            cmd: event.cmd_str.clone(),
+            gas_used: event.gas_used,
+            gas_cmd: event.gas_cmd,
+            position: event
+                .position
+                .as_ref()
+                .map(|pos| format!("{}:{}", pos.filename, pos.line)),
+            stack: with_stack.then(|| event.stack.iter().rev().map(|i| i.to_string()).collect()),
+        }
+    }
+
+    /// Records diverge if instructions or stack snapshots (if both are saved) differ,
+    /// source positions and gas are not compared.
+    fn diverges(&self, other: &TraceRecord) -> bool {
+        if self.cmd != other.cmd {
+            return true;
+        }
+        match (&self.stack, &other.stack) {
+            (Some(stack), Some(other)) => stack != other,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for TraceRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.step, self.gas_used, self.gas_cmd, self.cmd
+        )?;
+        if let Some(position) = &self.position {
+            write!(f, " {}", position)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn save_trace(filename: &str, trace: &[TraceEvent], with_stack: bool) -> Status {
+    let file = std::fs::File::create(filename)
+        .map_err(|e| format_err!("failed to create trace file {}: {}", filename, e))?;
+    let mut writer = BufWriter::new(file);
+    for event in trace {
+        let record = TraceRecord::from_event(event, with_stack);
+        writeln!(writer, "{}", serde_json::to_string(&record)?)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_trace(filename: &str) -> Result<Vec<TraceRecord>> {
+    let file = std::fs::File::open(filename)
+        .map_err(|e| format_err!("failed to open trace file {}: {}", filename, e))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()))
+        .map(|(index, line)| {
+            serde_json::from_str(&line?)
+                .map_err(|e| format_err!("{}:{}: invalid trace record: {}", filename, index + 1, e))
+        })
+        .collect()
+}
+
+/// Returns the index of the first diverged record, the length of the shorter
+/// trace if one is the prefix of the other and None for identical traces.
+pub fn find_divergence(first: &[TraceRecord], second: &[TraceRecord]) -> Option<usize> {
+    first
+        .iter()
+        .zip(second)
+        .position(|(a, b)| a.diverges(b))
+        .or_else(|| (first.len() != second.len()).then(|| first.len().min(second.len())))
+}
+
+/// Describes the first divergence of the traces with `context` preceding common records
+/// and `context` following records of each trace.
+pub fn diff_report(first: &[TraceRecord], second: &[TraceRecord], context: usize) -> String {
+    let index = match find_divergence(first, second) {
+        Some(index) => index,
+        None => return format!("Traces are identical ({} steps)\n", first.len()),
+    };
+    let mut report = format!("Traces diverge at record {}\n", index);
+    report += "Common:\n";
+    for record in &first[index.saturating_sub(context)..index] {
+        report += &format!("  {}\n", record);
+    }
+    for (title, trace) in [("First", first), ("Second", second)] {
+        report += &format!("{}:\n", title);
+        let tail = &trace[index.min(trace.len())..(index + context).min(trace.len())];
+        if tail.is_empty() {
+            report += "  <end of trace>\n";
+        }
+        for record in tail {
+            report += &format!("  {}\n", record);
+        }
+        if let Some(stack) = trace.get(index).and_then(|record| record.stack.as_ref()) {
+            report += "  stack:\n";
+            for item in stack {
+                report += &format!("    {}\n", item);
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(step: u32, cmd: &str) -> TraceRecord {
+        TraceRecord {
+            step,
+            cmd: cmd.to_string(),
+            gas_used: 18 * step as i64,
+            gas_cmd: 18,
+            position: Some(format!("contract.code:{}", step)),
+            stack: None,
+        }
+    }
+
+    #[test]
+    fn test_find_divergence() {
+        let first = vec![record(1, "PUSHINT 1"), record(2, "ADD"), record(3, "RET")];
+        let mut second = first.clone();
+        assert_eq!(find_divergence(&first, &second), None);
+        second[2].position = None;
+        assert_eq!(find_divergence(&first, &second), None);
+        second.pop();
+        assert_eq!(find_divergence(&first, &second), Some(2));
+        second.push(record(3, "SUB"));
+        assert_eq!(find_divergence(&first, &second), Some(2));
+        second[0].stack = Some(vec!["1".to_string()]);
+        assert_eq!(find_divergence(&first, &second), Some(2));
+        let mut first = first;
+        first[0].stack = Some(vec!["2".to_string()]);
+        assert_eq!(find_divergence(&first, &second), Some(0));
+    }
+
+    #[test]
+    fn test_diff_report() {
+        let first = vec![record(1, "PUSHINT 1"), record(2, "ADD")];
+        let second = vec![record(1, "PUSHINT 1"), record(2, "SUB")];
+        let report = diff_report(&first, &second, 5);
+        assert!(report.starts_with("Traces diverge at record 1\n"));
+        assert!(report.contains("First:\n  2 36 18 ADD contract.code:2\n"));
+        assert!(report.contains("Second:\n  2 36 18 SUB contract.code:2\n"));
+        assert_eq!(
+            diff_report(&first, &first, 5),
+            "Traces are identical (2 steps)\n"
+        );
+    }
+
+    #[test]
+    fn test_record_json() {
+        let json = serde_json::to_string(&record(1, "ADD")).unwrap();
+        assert_eq!(
+            json,
+            r#"{"step":1,"cmd":"ADD","gas_used":18,"gas_cmd":18,"position":"contract.code:1"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<TraceRecord>(&json).unwrap(),
+            record(1, "ADD")
+        );
+    }
+}
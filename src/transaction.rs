@@ -0,0 +1,250 @@
+/*
+ * Copyright 2018-2024 EverX Labs Ltd.
+ *
+ * Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+ * this file except in compliance with the License.
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific EVERX DEV software governing permissions and
+ * limitations under the License.
+ */
+use std::sync::{atomic::AtomicU64, Arc};
+
+use anyhow::format_err;
+use ever_block::{
+    Account, Cell, Deserializable, MsgAddressInt, Result, Serializable, StateInit, Transaction,
+    TransactionTickTock,
+};
+use ever_executor::{
+    BlockchainConfig, ExecuteParams, OrdinaryTransactionExecutor, TickTockTransactionExecutor,
+    TransactionExecutor,
+};
+use ever_vm::executor::{Engine, EngineTraceInfo};
+
+use crate::testcall::{
+    create_inbound_msg, decode_balance, get_selector, load_config_params, sign_body,
+    trace_callback, trace_callback_minimal, TestCallParams, TraceLevel,
+};
+
+const DEFAULT_BLOCK_LT: u64 = 1_000_000;
+
+pub struct TransactionResult {
+    pub transaction: Transaction,
+    pub account: Account,
+}
+
+impl TransactionResult {
+    /// Returns true if the transaction is not aborted.
+    pub fn is_success(&self) -> Result<bool> {
+        Ok(!self.transaction.read_description()?.is_aborted())
+    }
+
+    pub fn state_init(&self) -> Option<&StateInit> {
+        self.account.state_init()
+    }
+}
+
+fn blockchain_config(config: Option<&Cell>) -> Result<BlockchainConfig> {
+    match config {
+        Some(config_cell) => BlockchainConfig::with_config(load_config_params(config_cell)),
+        None => Ok(BlockchainConfig::default()),
+    }
+}
+
+/// Executes the whole transaction (storage, credit, compute, action and bounce phases)
+/// with the inbound message described by the params.
+pub fn execute_transaction(
+    addr: MsgAddressInt,
+    state_init: StateInit,
+    params: TestCallParams,
+) -> Result<TransactionResult> {
+    let (_, balance) = decode_balance(params.balance)?;
+    let now = params.msg_info.now;
+    let account = Account::active_by_init_code_hash(addr, balance, now, state_init, false)?;
+    execute_account_transaction(account, params)
+}
+
+/// Executes the whole transaction on the existing account state, the balance of
+/// the account is used instead of the balance in the params.
+pub fn execute_account_transaction(
+    account: Account,
+    params: TestCallParams,
+) -> Result<TransactionResult> {
+    let addr = account
+        .get_addr()
+        .cloned()
+        .ok_or_else(|| format_err!("account has no address"))?;
+    let config = blockchain_config(params.config.as_ref())?;
+    let now = params.msg_info.now;
+    let mut account_root = account.serialize()?;
+
+    let debug_info = params.debug_info;
+    let trace_level = params.trace_level;
+    let block_lt = match params.block_info.block_lt {
+        0 => DEFAULT_BLOCK_LT,
+        block_lt => block_lt,
+    };
+    let last_tr_lt = match params.block_info.trans_lt {
+        0 => block_lt,
+        trans_lt => trans_lt,
+    };
+    let exec_params = ExecuteParams {
+        block_unixtime: now,
+        block_lt,
+        last_tr_lt: Arc::new(AtomicU64::new(last_tr_lt)),
+        seed_block: params.block_info.rand_seed.clone(),
+        debug: trace_level != TraceLevel::None,
+        trace_callback: match trace_level {
+            TraceLevel::Full => Some(Arc::new(move |engine: &Engine, info: &EngineTraceInfo| {
+                trace_callback(engine, info, true, &debug_info);
+            })),
+            TraceLevel::Minimal => {
+                Some(Arc::new(move |engine: &Engine, info: &EngineTraceInfo| {
+                    trace_callback_minimal(engine, info, &debug_info);
+                }))
+            }
+            TraceLevel::None => None,
+        },
+        ..ExecuteParams::default()
+    };
+
+    let transaction = match params.ticktock {
+        Some(tt) => {
+            let tt = if tt == 0 {
+                TransactionTickTock::Tick
+            } else {
+                TransactionTickTock::Tock
+            };
+            TickTockTransactionExecutor::new(config, tt).execute_with_params(
+                None,
+                &mut account_root,
+                exec_params,
+            )?
+        }
+        None => {
+            let selector = get_selector(&params.msg_info, None);
+            let mut msg_info = params.msg_info;
+            if selector == -1 && msg_info.message.is_none() {
+                if let Some(key_file) = params.key_file {
+                    let mut body = msg_info.body.unwrap_or_default();
+                    sign_body(&mut body, key_file, params.abi_version, &addr)?;
+                    msg_info.body = Some(body);
+                }
+            }
+            let msg = create_inbound_msg(selector, &msg_info, &addr)?
+                .ok_or_else(|| format_err!("Failed to create message"))?;
+            OrdinaryTransactionExecutor::new(config).execute_with_params(
+                Some(&msg),
+                &mut account_root,
+                exec_params,
+            )?
+        }
+    };
+    let account = Account::construct_from_cell(account_root)?;
+
+    Ok(TransactionResult {
+        transaction,
+        account,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::printer::transaction_printer;
+    use crate::testcall::MsgInfo;
+    use ever_block::{BuilderData, CurrencyCollection};
+
+    const BALANCE: u128 = 10_000_000_000;
+    const VALUE: u128 = 1_000_000_000;
+
+    fn state_init(code: &[u8]) -> StateInit {
+        StateInit {
+            code: Some(
+                BuilderData::with_raw(code.to_vec(), code.len() * 8)
+                    .unwrap()
+                    .into_cell()
+                    .unwrap(),
+            ),
+            data: Some(Cell::default()),
+            ..Default::default()
+        }
+    }
+
+    fn params<'a>(value: &'a str, balance: &'a str) -> TestCallParams<'a> {
+        TestCallParams::new(MsgInfo {
+            balance: Some(value),
+            now: 1000,
+            bounce: Some(false),
+            ..Default::default()
+        })
+        .with_balance(balance)
+    }
+
+    fn balance_after_fees(result: &TransactionResult) -> u128 {
+        let balance = result.account.balance().unwrap().grams.as_u128();
+        let fees = result.transaction.total_fees().grams.as_u128();
+        assert!(fees > 0);
+        assert_eq!(balance, BALANCE + VALUE - fees);
+        balance
+    }
+
+    #[test]
+    fn test_transaction_success() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x11; 32].into()).unwrap();
+        // empty code returns successfully
+        let result = execute_transaction(
+            addr,
+            state_init(&[]),
+            params(&VALUE.to_string(), &BALANCE.to_string()),
+        )
+        .unwrap();
+        assert!(result.is_success().unwrap());
+        assert!(balance_after_fees(&result) > BALANCE);
+        assert!(result.state_init().is_some());
+        let printed = transaction_printer(&result.transaction).unwrap();
+        assert!(printed.contains("aborted: false"));
+        assert!(printed.contains("compute_phase"));
+    }
+
+    #[test]
+    fn test_uninit_account_deploy() {
+        let state_init = state_init(&[]);
+        let addr =
+            MsgAddressInt::with_standart(None, 0, state_init.hash().unwrap().into()).unwrap();
+        let account = Account::uninit(
+            addr,
+            0,
+            1000,
+            CurrencyCollection::with_grams(BALANCE as u64),
+        );
+        let mut params = params(&VALUE.to_string(), "1");
+        params.msg_info.state_init = Some(state_init.clone());
+        let result = execute_account_transaction(account, params).unwrap();
+        assert!(result.is_success().unwrap());
+        assert_eq!(result.state_init(), Some(&state_init));
+        balance_after_fees(&result);
+    }
+
+    #[test]
+    fn test_account_transaction_failure() {
+        let addr = MsgAddressInt::with_standart(None, 0, [0x22; 32].into()).unwrap();
+        // THROW 42
+        let account = Account::active_by_init_code_hash(
+            addr,
+            CurrencyCollection::with_grams(BALANCE as u64),
+            1000,
+            state_init(&[0xF2, 0x2A]),
+            false,
+        )
+        .unwrap();
+        // the balance in the params is ignored for the existing account
+        let result = execute_account_transaction(account, params(&VALUE.to_string(), "1")).unwrap();
+        assert!(!result.is_success().unwrap());
+        assert!(balance_after_fees(&result) < BALANCE + VALUE);
+        let printed = transaction_printer(&result.transaction).unwrap();
+        assert!(printed.contains("aborted: true"));
+    }
+}